@@ -2,7 +2,10 @@ use nvg::*;
 
 mod demo;
 
-struct DemoText;
+#[derive(Default)]
+struct DemoText {
+    label: Option<TextLayout>,
+}
 
 impl<R: Renderer> demo::Demo<R> for DemoText {
     fn update(&mut self, _width: f32, _height: f32, ctx: &mut Context<R>) -> anyhow::Result<()> {
@@ -44,13 +47,162 @@ impl<R: Renderer> demo::Demo<R> for DemoText {
         ctx.text_align(nvg::Align::BASELINE);
         ctx.text((200, 270), "baseline")?;
 
+        // pixel snapped small text
+        ctx.font_size(11.0);
+        ctx.text_rendering(TextRendering::Hinted);
+        ctx.text((5, 300), "hinted 11px")?;
+        ctx.text_rendering(TextRendering::Lcd);
+        ctx.text((80, 300), "lcd 11px")?;
+        ctx.text_rendering(TextRendering::Subpixel);
+        ctx.font_size(16.0);
+
         // spaces
         ctx.text((200, 300), "a b  c   d")?;
 
+        // decorations
+        ctx.text_decoration(TextDecoration::UNDERLINE | TextDecoration::SKIP_INK);
+        ctx.text((200, 320), "typography")?;
+        ctx.text_decoration(TextDecoration::LINE_THROUGH | TextDecoration::OVERLINE);
+        ctx.text((320, 320), "struck")?;
+        ctx.text_decoration(TextDecoration::empty());
+
+        // truncation
+        for (i, mode) in [
+            TruncationMode::End,
+            TruncationMode::Start,
+            TruncationMode::Middle,
+        ]
+        .iter()
+        .enumerate()
+        {
+            ctx.text_truncated(
+                (200, 110 + i as i32 * 20),
+                120.0,
+                "a long file name that does not fit.txt",
+                *mode,
+            )?;
+        }
+
+        // shadow
+        ctx.font_size(24.0);
+        ctx.font_blur(2.0);
+        ctx.fill_paint((0.0, 0.0, 0.0, 0.8));
+        ctx.text((5, 342), "shadow")?;
+        ctx.font_blur(0.0);
+        ctx.fill_paint((1.0, 1.0, 0.0));
+        ctx.text((5, 340), "shadow")?;
+        ctx.font_size(16.0);
+
+        // gradient fill
+        ctx.font_size(24.0);
+        ctx.fill_paint(Gradient::Linear {
+            start: (5, 385).into(),
+            end: (100, 405).into(),
+            start_color: (0.2, 0.6, 1.0).into(),
+            end_color: (1.0, 0.2, 0.6).into(),
+        });
+        ctx.text((5, 405), "gradient")?;
+        ctx.fill_paint((1.0, 1.0, 0.0));
+        ctx.font_size(16.0);
+
+        // distance field
+        ctx.save();
+        ctx.font_sdf(true);
+        ctx.translate(150.0, 380.0);
+        ctx.rotate(0.2);
+        ctx.scale(3.0, 3.0);
+        ctx.text((0, 0), "distance field")?;
+        ctx.restore();
+
+        // outlines
+        ctx.font_size(48.0);
+        ctx.begin_path();
+        ctx.text_path((5, 470), "outline");
+        ctx.fill_paint(Gradient::Linear {
+            start: (5, 430).into(),
+            end: (5, 470).into(),
+            start_color: (1.0, 1.0, 0.0).into(),
+            end_color: (1.0, 0.0, 0.0).into(),
+        });
+        ctx.fill()?;
+        ctx.stroke_paint((1.0, 1.0, 1.0));
+        ctx.stroke()?;
+        ctx.font_size(16.0);
+
+        // stroked text
+        ctx.save();
+        ctx.font_size(24.0);
+        ctx.stroke_paint((0.0, 0.0, 0.0));
+        ctx.stroke_width(3.0);
+        ctx.line_join(LineJoin::Round);
+        ctx.text_stroke((350, 340), "outlined caption")?;
+        ctx.fill_paint((1.0, 1.0, 1.0));
+        ctx.text((350, 340), "outlined caption")?;
+        ctx.restore();
+
+        // text box
+        ctx.text_align(nvg::Align::JUSTIFY | nvg::Align::TOP);
+        ctx.text_box(
+            (350, 20),
+            200.0,
+            "This is a longer chunk of text.\n\nWould have used lorem ipsum but she was busy jumping over the lazy dog with the fox and all the men who came to the aid of the party.",
+        )?;
+
+        // vertical text
+        ctx.text_writing_mode(WritingMode::Vertical);
+        ctx.text((580, 20), "vertical 縦書き")?;
+        ctx.text_writing_mode(WritingMode::Horizontal);
+
+        // rich text
+        let style = ctx.text_style();
+        let mut text = RichText::new();
+        text.push("Rich text mixes ", style)
+            .push(
+                "sizes",
+                TextStyle {
+                    font_size: 28.0,
+                    ..style
+                },
+            )
+            .push(", ", style)
+            .push(
+                "colored links",
+                TextStyle {
+                    fill: (0.4, 0.7, 1.0).into(),
+                    ..style
+                },
+            )
+            .push(" and ", style)
+            .push(
+                "spaced letters",
+                TextStyle {
+                    letter_spacing: 3.0,
+                    ..style
+                },
+            )
+            .push(" in one paragraph.", style);
+        ctx.rich_text((350, 200), 200.0, &text)?;
+
+        // font features
+        ctx.text_align(nvg::Align::LEFT | nvg::Align::BASELINE);
+        ctx.text((350, 540), "AVATAR kerned")?;
+        ctx.font_features(&[("kern", false)]);
+        ctx.text((350, 560), "AVATAR not kerned")?;
+        ctx.font_features(&[]);
+
+        // reused layout
+        ctx.text_align(nvg::Align::LEFT | nvg::Align::BASELINE);
+        let label = self
+            .label
+            .get_or_insert_with(|| ctx.text_layout("laid out once"));
+        for i in 0..3 {
+            ctx.draw_text_layout((5 + i * 110, 520), label)?;
+        }
+
         Ok(())
     }
 }
 
 fn main() {
-    demo::run(DemoText).unwrap();
+    demo::run(DemoText::default(), "demo-text");
 }
@@ -11,7 +11,9 @@ struct Shader {
     vert: gl::types::GLuint,
     loc_viewsize: i32,
     loc_tex: i32,
+    loc_mask: i32,
     loc_frag: u32,
+    dual_source: bool,
 }
 
 impl Drop for Shader {
@@ -68,6 +70,17 @@ impl Shader {
         gl::BindAttribLocation(prog, 0, name_vertex.as_ptr() as *const i8);
         gl::BindAttribLocation(prog, 1, name_tcoord.as_ptr() as *const i8);
 
+        // The second output is only used as the blend factor of LCD text.
+        let name_color = std::ffi::CString::new("outColor").unwrap();
+        let name_blend = std::ffi::CString::new("outBlend").unwrap();
+        let dual_source = gl::BindFragDataLocationIndexed::is_loaded();
+        if dual_source {
+            gl::BindFragDataLocationIndexed(prog, 0, 0, name_color.as_ptr());
+            gl::BindFragDataLocationIndexed(prog, 0, 1, name_blend.as_ptr());
+        } else {
+            gl::BindFragDataLocation(prog, 0, name_color.as_ptr());
+        }
+
         gl::LinkProgram(prog);
         gl::GetProgramiv(prog, gl::LINK_STATUS, &mut status);
         if status != gl::TRUE as i32 {
@@ -76,6 +89,7 @@ impl Shader {
 
         let name_viewsize = std::ffi::CString::new("viewSize").unwrap();
         let name_tex = std::ffi::CString::new("tex").unwrap();
+        let name_mask = std::ffi::CString::new("mask").unwrap();
         let name_frag = std::ffi::CString::new("frag").unwrap();
 
         Ok(Shader {
@@ -84,7 +98,9 @@ impl Shader {
             vert,
             loc_viewsize: gl::GetUniformLocation(prog, name_viewsize.as_ptr() as *const i8),
             loc_tex: gl::GetUniformLocation(prog, name_tex.as_ptr() as *const i8),
+            loc_mask: gl::GetUniformLocation(prog, name_mask.as_ptr()),
             loc_frag: gl::GetUniformBlockIndex(prog, name_frag.as_ptr() as *const i8),
+            dual_source,
         })
     }
 }
@@ -102,6 +118,7 @@ enum CallType {
     ConvexFill,
     Stroke,
     Triangles,
+    Text,
 }
 
 struct Blend {
@@ -125,6 +142,7 @@ impl From<CompositeOperationState> for Blend {
 struct Call {
     call_type: CallType,
     image: Option<usize>,
+    mask: Option<usize>,
     path_offset: usize,
     path_count: usize,
     triangle_offset: usize,
@@ -170,6 +188,7 @@ struct FragUniforms {
     stroke_thr: f32,
     tex_type: i32,
     type_: i32,
+    mask_type: i32,
 }
 
 pub struct Renderer {
@@ -370,6 +389,21 @@ impl Renderer {
         );
     }
 
+    unsafe fn do_text(&self, call: &Call) {
+        self.set_uniforms(call.uniform_offset, call.image);
+        gl::ActiveTexture(gl::TEXTURE1);
+        if let Some(texture) = call.mask.and_then(|mask| self.textures.get(mask)) {
+            gl::BindTexture(gl::TEXTURE_2D, texture.tex);
+        }
+        gl::DrawArrays(
+            gl::TRIANGLES,
+            call.triangle_offset as i32,
+            call.triangle_count as i32,
+        );
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
     fn convert_paint(
         &self,
         paint: &Paint,
@@ -392,6 +426,7 @@ impl Renderer {
             stroke_thr,
             tex_type: 0,
             type_: 0,
+            mask_type: 0,
         };
 
         if scissor.extent.width < -0.5 || scissor.extent.height < -0.5 {
@@ -438,7 +473,20 @@ impl Renderer {
                             1
                         }
                     }
-                    TextureType::Alpha => frag.tex_type = 2,
+                    TextureType::RGB => {
+                        frag.tex_type = if texture.flags.contains(ImageFlags::LCD) {
+                            5
+                        } else {
+                            0
+                        }
+                    }
+                    TextureType::Alpha => {
+                        frag.tex_type = if texture.flags.contains(ImageFlags::SDF) {
+                            3
+                        } else {
+                            2
+                        }
+                    }
                 }
             }
         } else {
@@ -500,6 +548,22 @@ impl renderer::Renderer for Renderer {
                         },
                     );
                 }
+                TextureType::RGB => {
+                    gl::TexImage2D(
+                        gl::TEXTURE_2D,
+                        0,
+                        gl::RGB8 as i32,
+                        width as i32,
+                        height as i32,
+                        0,
+                        gl::RGB,
+                        gl::UNSIGNED_BYTE,
+                        match data {
+                            Some(data) => data.as_ptr() as *const c_void,
+                            None => std::ptr::null(),
+                        },
+                    );
+                }
                 TextureType::Alpha => {
                     gl::TexImage2D(
                         gl::TEXTURE_2D,
@@ -614,6 +678,17 @@ impl renderer::Renderer for Renderer {
                         gl::UNSIGNED_BYTE,
                         data.as_ptr() as *const c_void,
                     ),
+                    TextureType::RGB => gl::TexSubImage2D(
+                        gl::TEXTURE_2D,
+                        0,
+                        x as i32,
+                        y as i32,
+                        width as i32,
+                        height as i32,
+                        gl::RGB,
+                        gl::UNSIGNED_BYTE,
+                        data.as_ptr() as *const c_void,
+                    ),
                     TextureType::Alpha => gl::TexSubImage2D(
                         gl::TEXTURE_2D,
                         0,
@@ -711,6 +786,7 @@ impl renderer::Renderer for Renderer {
                 );
 
                 gl::Uniform1i(self.shader.loc_tex, 0);
+                gl::Uniform1i(self.shader.loc_mask, 1);
                 gl::Uniform2fv(
                     self.shader.loc_viewsize,
                     1,
@@ -734,6 +810,7 @@ impl renderer::Renderer for Renderer {
                         CallType::ConvexFill => self.do_convex_fill(&call),
                         CallType::Stroke => self.do_stroke(&call),
                         CallType::Triangles => self.do_triangles(&call),
+                        CallType::Text => self.do_text(call),
                     }
                 }
 
@@ -766,6 +843,7 @@ impl renderer::Renderer for Renderer {
         let mut call = Call {
             call_type: CallType::Fill,
             image: paint.image,
+            mask: None,
             path_offset: self.paths.len(),
             path_count: paths.len(),
             triangle_offset: 0,
@@ -845,6 +923,7 @@ impl renderer::Renderer for Renderer {
         let mut call = Call {
             call_type: CallType::Stroke,
             image: paint.image,
+            mask: None,
             path_offset: self.paths.len(),
             path_count: paths.len(),
             triangle_offset: 0,
@@ -896,6 +975,7 @@ impl renderer::Renderer for Renderer {
         let call = Call {
             call_type: CallType::Triangles,
             image: paint.image,
+            mask: None,
             path_offset: 0,
             path_count: 0,
             triangle_offset: self.vertexes.len(),
@@ -912,6 +992,64 @@ impl renderer::Renderer for Renderer {
         self.append_uniforms(uniforms);
         Ok(())
     }
+
+    fn text(
+        &mut self,
+        paint: &Paint,
+        composite_operation: CompositeOperationState,
+        scissor: &Scissor,
+        mask: ImageId,
+        vertexes: &[Vertex],
+    ) -> anyhow::Result<()> {
+        let mut call = Call {
+            call_type: CallType::Text,
+            image: paint.image,
+            mask: Some(mask),
+            path_offset: 0,
+            path_count: 0,
+            triangle_offset: self.vertexes.len(),
+            triangle_count: vertexes.len(),
+            uniform_offset: self.uniforms.len() / self.frag_size,
+            blend_func: composite_operation.into(),
+        };
+
+        let mut uniforms = self.convert_paint(paint, scissor, 1.0, 1.0, -1.0);
+        let texture = match self.textures.get(mask) {
+            Some(texture) => texture,
+            None => bail!("texture '{}' not found", mask),
+        };
+        uniforms.mask_type = match texture.texture_type {
+            TextureType::Alpha if texture.flags.contains(ImageFlags::SDF) => 2,
+            TextureType::RGB if texture.flags.contains(ImageFlags::LCD) => 3,
+            _ => 1,
+        };
+
+        // LCD text is blended per channel with dual-source blending, it is drawn in grayscale
+        // when that is not supported or another composite operation is used.
+        let source_over = matches!(
+            composite_operation,
+            CompositeOperationState {
+                src_rgb: BlendFactor::One,
+                dst_rgb: BlendFactor::OneMinusSrcAlpha,
+                src_alpha: BlendFactor::One,
+                dst_alpha: BlendFactor::OneMinusSrcAlpha,
+            }
+        );
+        if uniforms.mask_type == 3 && source_over && self.shader.dual_source {
+            uniforms.mask_type = 4;
+            call.blend_func = Blend {
+                src_rgb: gl::ONE,
+                dst_rgb: gl::ONE_MINUS_SRC1_COLOR,
+                src_alpha: gl::ONE,
+                dst_alpha: gl::ONE_MINUS_SRC_ALPHA,
+            };
+        }
+
+        self.calls.push(call);
+        self.vertexes.extend(vertexes);
+        self.append_uniforms(uniforms);
+        Ok(())
+    }
 }
 
 fn shader_error(shader: gl::types::GLuint, filename: &str) -> anyhow::Error {
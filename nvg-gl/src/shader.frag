@@ -16,12 +16,15 @@ layout(std140) uniform frag {
     float strokeThr;
     int texType;
     int type;
+    int maskType;
 };
 
 uniform sampler2D tex;
+uniform sampler2D mask;
 in vec2 ftcoord;
 in vec2 fpos;
 out vec4 outColor;
+out vec4 outBlend;
 
 float sdroundrect(vec2 pt, vec2 ext, float rad) {
     vec2 ext2 = ext - vec2(rad,rad);
@@ -41,8 +44,9 @@ float strokeMask() {
 
 void main(void) {
     vec4 result;
+    outBlend = vec4(0.0);
     float scissor = scissorMask(fpos);
-    float strokeAlpha = strokeMask();
+    float strokeAlpha = maskType == 0 ? strokeMask() : 1.0;
     if (strokeAlpha < strokeThr) discard;
 
     if (type == 0) {
@@ -58,6 +62,11 @@ void main(void) {
         vec4 color = texture(tex, pt);
         if (texType == 1) color = vec4(color.xyz * color.w, color.w);
         if (texType == 2) color = vec4(color.x);
+        if (texType == 5) color = vec4((color.x + color.y + color.z) / 3.0);
+        if (texType == 3) {
+            float w = fwidth(color.x);
+            color = vec4(smoothstep(0.5 - w, 0.5 + w, color.x));
+        }
         color *= innerCol;
         color *= strokeAlpha * scissor;
         result = color;
@@ -69,9 +78,32 @@ void main(void) {
         vec4 color = texture(tex, ftcoord);
         if (texType == 1) color = vec4(color.xyz * color.w, color.w);
         if (texType == 2) color = vec4(color.x);
+        if (texType == 5) color = vec4((color.x + color.y + color.z) / 3.0);
+        if (texType == 3) {
+            float w = fwidth(color.x);
+            color = vec4(smoothstep(0.5 - w, 0.5 + w, color.x));
+        }
         color *= scissor;
         result = color * innerCol;
     }
 
+    if (maskType != 0) {
+        // Text, the paint is masked by the glyph coverage.
+        vec4 coverage = texture(mask, ftcoord);
+        if (maskType == 1) coverage = vec4(coverage.x);
+        if (maskType == 2) {
+            float w = fwidth(coverage.x);
+            coverage = vec4(smoothstep(0.5 - w, 0.5 + w, coverage.x));
+        }
+        if (maskType == 3) coverage = vec4((coverage.x + coverage.y + coverage.z) / 3.0);
+        if (maskType == 4) {
+            // LCD text, every channel is blended with its own coverage.
+            outBlend = vec4(coverage.xyz * result.w, result.w);
+            result = vec4(coverage.xyz * result.xyz, max(max(coverage.x, coverage.y), coverage.z) * result.w);
+        } else {
+            result *= coverage.x;
+        }
+    }
+
     outColor = result;
 }
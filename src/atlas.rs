@@ -0,0 +1,235 @@
+use crate::context::ImageId;
+use crate::renderer::TextureType;
+use crate::{ImageFlags, Renderer};
+
+#[derive(Debug, Copy, Clone)]
+struct AtlasNode {
+    x: usize,
+    y: usize,
+    width: usize,
+}
+
+fn bytes_per_pixel(texture_type: TextureType) -> usize {
+    match texture_type {
+        TextureType::RGBA => 4,
+        TextureType::RGB => 3,
+        TextureType::Alpha => 1,
+    }
+}
+
+pub(crate) struct Atlas {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    texture_type: TextureType,
+    flags: ImageFlags,
+    nodes: Vec<AtlasNode>,
+    data: Vec<u8>,
+    dirty: Option<(usize, usize, usize, usize)>,
+}
+
+impl Atlas {
+    pub fn new(width: usize, height: usize, texture_type: TextureType, flags: ImageFlags) -> Atlas {
+        Atlas {
+            width,
+            height,
+            texture_type,
+            flags,
+            nodes: vec![AtlasNode { x: 0, y: 0, width }],
+            data: vec![0; width * height * bytes_per_pixel(texture_type)],
+            dirty: None,
+        }
+    }
+
+    // Glyphs are packed without gaps, so the pixels of the old glyphs are cleared as well.
+    pub fn reset(&mut self) {
+        self.data.fill(0);
+        self.nodes.clear();
+        self.nodes.push(AtlasNode {
+            x: 0,
+            y: 0,
+            width: self.width,
+        });
+    }
+
+    pub fn expand(&mut self, width: usize, height: usize) {
+        let bpp = bytes_per_pixel(self.texture_type);
+        let mut data = vec![0; width * height * bpp];
+        for y in 0..self.height {
+            data[y * width * bpp..(y * width + self.width) * bpp]
+                .copy_from_slice(&self.data[y * self.width * bpp..(y + 1) * self.width * bpp]);
+        }
+
+        if width > self.width {
+            self.nodes.push(AtlasNode {
+                x: self.width,
+                y: 0,
+                width: width - self.width,
+            });
+        }
+
+        self.width = width;
+        self.height = height;
+        self.data = data;
+    }
+
+    fn rect_fits(&self, mut i: usize, width: usize, height: usize) -> Option<usize> {
+        let x = self.nodes[i].x;
+        let mut y = self.nodes[i].y;
+
+        if x + width > self.width {
+            return None;
+        }
+
+        let mut space_left = width as isize;
+        while space_left > 0 {
+            let node = self.nodes.get(i)?;
+            y = y.max(node.y);
+            if y + height > self.height {
+                return None;
+            }
+            space_left -= node.width as isize;
+            i += 1;
+        }
+
+        Some(y)
+    }
+
+    fn add_skyline_level(&mut self, idx: usize, x: usize, y: usize, width: usize, height: usize) {
+        self.nodes.insert(
+            idx,
+            AtlasNode {
+                x,
+                y: y + height,
+                width,
+            },
+        );
+
+        // Shrink or remove the nodes covered by the new level.
+        let i = idx + 1;
+        while i < self.nodes.len() {
+            let prev = self.nodes[i - 1];
+            let node = &mut self.nodes[i];
+            if node.x < prev.x + prev.width {
+                let shrink = prev.x + prev.width - node.x;
+                if shrink >= node.width {
+                    self.nodes.remove(i);
+                    continue;
+                }
+                node.x += shrink;
+                node.width -= shrink;
+            }
+            break;
+        }
+
+        // Merge nodes of the same height.
+        let mut i = 0;
+        while i + 1 < self.nodes.len() {
+            if self.nodes[i].y == self.nodes[i + 1].y {
+                self.nodes[i].width += self.nodes[i + 1].width;
+                self.nodes.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    pub fn add_rect(&mut self, width: usize, height: usize) -> Option<(usize, usize)> {
+        let mut best: Option<(usize, usize, usize, usize, usize)> = None;
+
+        for i in 0..self.nodes.len() {
+            if let Some(y) = self.rect_fits(i, width, height) {
+                let node = self.nodes[i];
+                let better = match best {
+                    Some((_, _, _, best_width, best_height)) => {
+                        y + height < best_height
+                            || (y + height == best_height && node.width < best_width)
+                    }
+                    None => true,
+                };
+                if better {
+                    best = Some((i, node.x, y, node.width, y + height));
+                }
+            }
+        }
+
+        let (i, x, y, _, _) = best?;
+        self.add_skyline_level(i, x, y, width, height);
+        Some((x, y))
+    }
+
+    pub fn write(&mut self, x: usize, y: usize, width: usize, height: usize, data: &[u8]) {
+        let bpp = bytes_per_pixel(self.texture_type);
+        for row in 0..height {
+            let offset = ((y + row) * self.width + x) * bpp;
+            self.data[offset..offset + width * bpp]
+                .copy_from_slice(&data[row * width * bpp..(row + 1) * width * bpp]);
+        }
+
+        self.dirty = Some(match self.dirty {
+            Some((min_x, min_y, max_x, max_y)) => (
+                min_x.min(x),
+                min_y.min(y),
+                max_x.max(x + width),
+                max_y.max(y + height),
+            ),
+            None => (x, y, x + width, y + height),
+        });
+    }
+
+    pub fn create_texture<R: Renderer>(&mut self, renderer: &mut R) -> anyhow::Result<ImageId> {
+        self.dirty = None;
+        renderer.create_texture(
+            self.texture_type,
+            self.width,
+            self.height,
+            self.flags,
+            Some(&self.data),
+        )
+    }
+
+    pub fn flush<R: Renderer>(&mut self, renderer: &mut R, img: ImageId) -> anyhow::Result<()> {
+        if let Some((min_x, min_y, max_x, max_y)) = self.dirty.take() {
+            let bpp = bytes_per_pixel(self.texture_type);
+            let mut data = Vec::with_capacity((max_x - min_x) * (max_y - min_y) * bpp);
+            for y in min_y..max_y {
+                data.extend_from_slice(
+                    &self.data[(y * self.width + min_x) * bpp..(y * self.width + max_x) * bpp],
+                );
+            }
+            renderer.update_texture(img, min_x, min_y, max_x - min_x, max_y - min_y, &data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_rect_merges_levels_of_the_same_height() {
+        let mut atlas = Atlas::new(16, 16, TextureType::Alpha, ImageFlags::empty());
+        assert_eq!(atlas.add_rect(4, 4), Some((0, 0)));
+        assert_eq!(atlas.add_rect(4, 4), Some((4, 0)));
+        assert_eq!(atlas.nodes.len(), 2);
+        assert_eq!((atlas.nodes[0].y, atlas.nodes[0].width), (4, 8));
+
+        // Rects go to the lowest level, levels reaching the same height merge again.
+        assert_eq!(atlas.add_rect(8, 2), Some((8, 0)));
+        assert_eq!(atlas.nodes.len(), 2);
+        assert_eq!(atlas.add_rect(8, 2), Some((8, 2)));
+        assert_eq!(atlas.nodes.len(), 1);
+        assert_eq!(atlas.add_rect(16, 12), Some((0, 4)));
+        assert_eq!(atlas.add_rect(1, 1), None);
+    }
+
+    #[test]
+    fn reset_clears_pixels() {
+        let mut atlas = Atlas::new(8, 8, TextureType::Alpha, ImageFlags::empty());
+        let (x, y) = atlas.add_rect(4, 4).unwrap();
+        atlas.write(x, y, 4, 4, &[255; 16]);
+        atlas.reset();
+        assert!(atlas.data.iter().all(|v| *v == 0));
+        assert_eq!(atlas.add_rect(8, 8), Some((0, 0)));
+    }
+}
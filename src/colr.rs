@@ -0,0 +1,135 @@
+use ttf_parser::{Face, Tag};
+
+pub(crate) struct ColorLayers {
+    base_glyphs: Vec<(u16, usize, usize)>,
+    layers: Vec<(u16, u16)>,
+    palette: Vec<[u8; 4]>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+impl ColorLayers {
+    pub fn parse(face: &Face) -> Option<ColorLayers> {
+        ColorLayers::read(
+            face.table_data(Tag::from_bytes(b"COLR"))?,
+            face.table_data(Tag::from_bytes(b"CPAL"))?,
+        )
+    }
+
+    fn read(colr: &[u8], cpal: &[u8]) -> Option<ColorLayers> {
+        let num_base_glyphs = read_u16(colr, 2)? as usize;
+        let base_glyphs_offset = read_u32(colr, 4)? as usize;
+        let layers_offset = read_u32(colr, 8)? as usize;
+        let num_layers = read_u16(colr, 12)? as usize;
+
+        let mut base_glyphs = Vec::with_capacity(num_base_glyphs);
+        for i in 0..num_base_glyphs {
+            let offset = base_glyphs_offset + i * 6;
+            base_glyphs.push((
+                read_u16(colr, offset)?,
+                read_u16(colr, offset + 2)? as usize,
+                read_u16(colr, offset + 4)? as usize,
+            ));
+        }
+        base_glyphs.sort_unstable_by_key(|(glyph, _, _)| *glyph);
+
+        let mut layers = Vec::with_capacity(num_layers);
+        for i in 0..num_layers {
+            let offset = layers_offset + i * 4;
+            layers.push((read_u16(colr, offset)?, read_u16(colr, offset + 2)?));
+        }
+
+        // Only the first palette is used, its colors are stored as BGRA.
+        let num_palette_entries = read_u16(cpal, 2)? as usize;
+        let color_records_offset = read_u32(cpal, 8)? as usize;
+        let first_color_index = read_u16(cpal, 12)? as usize;
+        let mut palette = Vec::with_capacity(num_palette_entries);
+        for i in 0..num_palette_entries {
+            let offset = color_records_offset + (first_color_index + i) * 4;
+            let bgra = cpal.get(offset..offset + 4)?;
+            palette.push([bgra[2], bgra[1], bgra[0], bgra[3]]);
+        }
+
+        Some(ColorLayers {
+            base_glyphs,
+            layers,
+            palette,
+        })
+    }
+
+    pub fn contains(&self, glyph: u16) -> bool {
+        self.base_glyphs
+            .binary_search_by_key(&glyph, |(glyph, _, _)| *glyph)
+            .is_ok()
+    }
+
+    pub fn layers(&self, glyph: u16) -> impl Iterator<Item = (u16, [u8; 4])> + '_ {
+        let range = match self
+            .base_glyphs
+            .binary_search_by_key(&glyph, |(glyph, _, _)| *glyph)
+        {
+            Ok(n) => {
+                let (_, first, count) = self.base_glyphs[n];
+                first.min(self.layers.len())..(first + count).min(self.layers.len())
+            }
+            Err(_) => 0..0,
+        };
+
+        // The palette index 0xFFFF selects the text color, color glyphs are drawn untinted so
+        // these layers are drawn in white.
+        self.layers[range].iter().map(move |(glyph, index)| {
+            (
+                *glyph,
+                self.palette
+                    .get(*index as usize)
+                    .copied()
+                    .unwrap_or([255, 255, 255, 255]),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One base glyph with two layers and a palette of red and blue.
+    const COLR: &[u8] = &[
+        0, 0, 0, 1, 0, 0, 0, 14, 0, 0, 0, 20, 0, 2, // header
+        0, 5, 0, 0, 0, 2, // base glyph
+        0, 7, 0, 0, 0, 8, 0, 1, // layers
+    ];
+    const CPAL: &[u8] = &[
+        0, 0, 0, 2, 0, 1, 0, 2, 0, 0, 0, 14, 0, 0, // header
+        0, 0, 255, 255, 255, 0, 0, 255, // colors
+    ];
+
+    #[test]
+    fn read_layers() {
+        let colr = ColorLayers::read(COLR, CPAL).unwrap();
+        assert!(colr.contains(5));
+        assert!(!colr.contains(7));
+        assert_eq!(
+            colr.layers(5).collect::<Vec<_>>(),
+            [(7, [255, 0, 0, 255]), (8, [0, 0, 255, 255])]
+        );
+    }
+
+    #[test]
+    fn read_truncated_tables() {
+        for len in 0..COLR.len() {
+            assert!(ColorLayers::read(&COLR[..len], CPAL).is_none());
+        }
+        for len in 0..CPAL.len() {
+            assert!(ColorLayers::read(COLR, &CPAL[..len]).is_none());
+        }
+    }
+}
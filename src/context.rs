@@ -1,7 +1,14 @@
 use crate::cache::PathCache;
-use crate::fonts::{FontId, Fonts, LayoutChar};
+use crate::font_backend::{Contour, FontBackend, Segment, Tag};
+use crate::fonts::{
+    is_newline, AtlasKind, FontId, FontInfo, Fonts, GlyphPosition, LayoutChar, TextLayout, TextRow,
+    TextSpacing, TruncatedText, TruncationMode, ELLIPSIS,
+};
+#[cfg(feature = "rusttype")]
+use crate::fonts::{MAX_TEX_HEIGHT, MAX_TEX_WIDTH, TEX_HEIGHT, TEX_WIDTH};
 use crate::renderer::{Renderer, Scissor, TextureType};
-use crate::{Color, Extent, Point, Rect, Transform};
+use crate::rich_text::{RichText, RichTextRow, TextOffset, TextStyle};
+use crate::{Bounds, Color, Extent, Point, Rect, Transform};
 use clamped::Clamp;
 use std::f32::consts::PI;
 
@@ -200,6 +207,7 @@ bitflags! {
         const MIDDLE = 0x10;
         const BOTTOM = 0x20;
         const BASELINE = 0x40;
+        const JUSTIFY = 0x80;
     }
 }
 
@@ -316,6 +324,35 @@ pub struct CompositeOperationState {
     pub dst_alpha: BlendFactor,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WritingMode {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TextRendering {
+    // Glyphs keep their fractional positions, this suits animated text.
+    Subpixel,
+    // Glyph origins and baselines are snapped to device pixels.
+    PixelSnapped,
+    // Also rounds advances and spacing to whole pixels and grid fits the outlines vertically,
+    // the x-height is scaled to whole pixels. Stems are not adjusted.
+    Hinted,
+    // Pixel snapped glyphs rasterized for the RGB stripes of LCD panels, only meant for text
+    // on opaque backgrounds.
+    Lcd,
+}
+
+bitflags! {
+    pub struct TextDecoration: u32 {
+        const UNDERLINE = 0x1;
+        const OVERLINE = 0x2;
+        const LINE_THROUGH = 0x4;
+        const SKIP_INK = 0x8;
+    }
+}
+
 bitflags! {
     pub struct ImageFlags: u32 {
         const GENERATE_MIPMAPS = 0x1;
@@ -324,6 +361,8 @@ bitflags! {
         const FLIPY	= 0x8;
         const PREMULTIPLIED = 0x10;
         const NEAREST = 0x20;
+        const SDF = 0x40;
+        const LCD = 0x80;
     }
 }
 
@@ -373,11 +412,17 @@ impl Path {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct TextMetrics {
     pub ascender: f32,
     pub descender: f32,
     pub line_gap: f32,
+    pub x_height: f32,
+    pub cap_height: f32,
+    pub underline_position: f32,
+    pub underline_thickness: f32,
+    pub strikeout_position: f32,
+    pub strikeout_thickness: f32,
 }
 
 impl TextMetrics {
@@ -400,10 +445,20 @@ struct State {
     xform: Transform,
     scissor: Scissor,
     font_size: f32,
+    font_blur: f32,
+    font_sdf: bool,
     letter_spacing: f32,
+    word_spacing: f32,
+    tab_stops: Vec<f32>,
+    tab_width: f32,
     line_height: f32,
     text_align: Align,
+    text_decoration: TextDecoration,
+    writing_mode: WritingMode,
+    text_rendering: TextRendering,
     font_id: FontId,
+    font_variations: Vec<(Tag, f32)>,
+    font_features: Vec<(Tag, bool)>,
 }
 
 impl Default for State {
@@ -427,12 +482,70 @@ impl Default for State {
                 },
             },
             font_size: 16.0,
+            font_blur: 0.0,
+            font_sdf: false,
             letter_spacing: 0.0,
+            word_spacing: 0.0,
+            tab_stops: Vec::new(),
+            tab_width: 0.0,
             line_height: 1.0,
             text_align: Align::LEFT | Align::BASELINE,
+            text_decoration: TextDecoration::empty(),
+            writing_mode: WritingMode::Horizontal,
+            text_rendering: TextRendering::Subpixel,
             font_id: 0,
+            font_variations: Vec::new(),
+            font_features: Vec::new(),
+        }
+    }
+}
+
+impl State {
+    fn text_spacing(&self, scale: f32) -> TextSpacing {
+        let spacing = self.span_spacing(self.letter_spacing, scale);
+        TextSpacing {
+            word: if spacing.pixel_advances {
+                (self.word_spacing * scale).round()
+            } else {
+                self.word_spacing * scale
+            },
+            tab_stops: self.tab_stops.iter().map(|x| x * scale).collect(),
+            tab_width: self.tab_width * scale,
+            ..spacing
+        }
+    }
+
+    // Rich text spans only carry a letter spacing.
+    fn span_spacing(&self, letter_spacing: f32, scale: f32) -> TextSpacing {
+        let pixel_advances = self.text_rendering == TextRendering::Hinted;
+        TextSpacing {
+            letter: if pixel_advances {
+                (letter_spacing * scale).round()
+            } else {
+                letter_spacing * scale
+            },
+            pixel_advances,
+            features: self.font_features.clone(),
+            ..Default::default()
         }
     }
+
+    // The fractional device pixel offset of the text layout origin, only text that is not
+    // rotated or skewed is snapped to device pixels.
+    fn pixel_grid(&self, device_pixel_ratio: f32) -> Option<Point> {
+        let t = &self.xform.0;
+        if self.text_rendering == TextRendering::Subpixel
+            || t[1] != 0.0
+            || t[2] != 0.0
+            || t[0] <= 0.0
+            || t[3] <= 0.0
+        {
+            return None;
+        }
+        let x = t[4] * device_pixel_ratio;
+        let y = t[5] * device_pixel_ratio;
+        Some(Point::new(x - x.floor(), y - y.floor()))
+    }
 }
 
 #[derive(Debug)]
@@ -447,15 +560,18 @@ pub(crate) enum Command {
 pub struct Context<R: Renderer> {
     renderer: R,
     commands: Vec<Command>,
+    path_commands: Vec<Command>,
     last_position: Point,
     states: Vec<State>,
     cache: PathCache,
+    path_cache: PathCache,
     tess_tol: f32,
     dist_tol: f32,
     fringe_width: f32,
     device_pixel_ratio: f32,
     fonts: Fonts,
     layout_chars: Vec<LayoutChar>,
+    text_rows: Vec<TextRow>,
     draw_call_count: usize,
     fill_triangles_count: usize,
     stroke_triangles_count: usize,
@@ -463,20 +579,56 @@ pub struct Context<R: Renderer> {
 }
 
 impl<R: Renderer> Context<R> {
-    pub fn create(mut renderer: R) -> anyhow::Result<Context<R>> {
-        let fonts = Fonts::new(&mut renderer)?;
+    #[cfg(feature = "rusttype")]
+    pub fn create(renderer: R) -> anyhow::Result<Context<R>> {
+        Self::create_with_font_atlas(
+            renderer,
+            (TEX_WIDTH, TEX_HEIGHT),
+            (MAX_TEX_WIDTH, MAX_TEX_HEIGHT),
+        )
+    }
+
+    #[cfg(feature = "rusttype")]
+    pub fn create_with_font_atlas(
+        renderer: R,
+        font_atlas_size: (usize, usize),
+        max_font_atlas_size: (usize, usize),
+    ) -> anyhow::Result<Context<R>> {
+        Self::create_with_font_backend(
+            renderer,
+            font_atlas_size,
+            max_font_atlas_size,
+            crate::RusttypeBackend,
+        )
+    }
+
+    pub fn create_with_font_backend<B: FontBackend + 'static>(
+        mut renderer: R,
+        font_atlas_size: (usize, usize),
+        max_font_atlas_size: (usize, usize),
+        backend: B,
+    ) -> anyhow::Result<Context<R>> {
+        let fonts = Fonts::new(
+            &mut renderer,
+            font_atlas_size,
+            max_font_atlas_size,
+            Box::new(backend),
+        )?;
         Ok(Context {
             renderer,
             commands: Default::default(),
+            path_commands: Default::default(),
             last_position: Default::default(),
             states: vec![Default::default()],
             cache: Default::default(),
+            path_cache: Default::default(),
             tess_tol: 0.0,
             dist_tol: 0.0,
             fringe_width: 0.0,
             device_pixel_ratio: 0.0,
             fonts,
             layout_chars: Default::default(),
+            text_rows: Default::default(),
             draw_call_count: 0,
             fill_triangles_count: 0,
             stroke_triangles_count: 0,
@@ -499,6 +651,7 @@ impl<R: Renderer> Context<R> {
         self.states.clear();
         self.states.push(Default::default());
         self.set_device_pixel_ratio(device_pixel_ratio);
+        self.fonts.delete_retired_images(&mut self.renderer)?;
         self.renderer
             .viewport(window_extent.into(), device_pixel_ratio)?;
         self.draw_call_count = 0;
@@ -540,6 +693,12 @@ impl<R: Renderer> Context<R> {
         self.states.last_mut().unwrap()
     }
 
+    // The font of the current state, an instance with the variations applied for variable fonts.
+    fn state_font(&self) -> FontId {
+        let state = self.states.last().unwrap();
+        self.fonts.instance(state.font_id, &state.font_variations)
+    }
+
     pub fn reset(&mut self) {
         *self.state_mut() = Default::default();
     }
@@ -1132,13 +1291,54 @@ impl<R: Renderer> Context<R> {
         name: N,
         data: D,
     ) -> anyhow::Result<FontId> {
-        self.fonts.add_font(name, data)
+        self.fonts.add_font(name, data, 0)
+    }
+
+    pub fn create_font_from_file_at<N: Into<String>, P: AsRef<std::path::Path>>(
+        &mut self,
+        name: N,
+        path: P,
+        index: u32,
+    ) -> anyhow::Result<FontId> {
+        self.create_font_at(name, std::fs::read(path)?, index)
+    }
+
+    pub fn create_font_at<N: Into<String>, D: Into<Vec<u8>>>(
+        &mut self,
+        name: N,
+        data: D,
+        index: u32,
+    ) -> anyhow::Result<FontId> {
+        self.fonts.add_font(name, data, index)
+    }
+
+    #[cfg(feature = "system-fonts")]
+    pub fn load_system_fonts(&mut self) {
+        self.fonts.load_system_fonts();
+    }
+
+    #[cfg(feature = "system-fonts")]
+    pub fn load_fonts_dir<P: AsRef<std::path::Path>>(&mut self, dir: P) {
+        self.fonts.load_fonts_dir(dir.as_ref());
     }
 
     pub fn find_font<N: AsRef<str>>(&self, name: N) -> Option<FontId> {
         self.fonts.find(name.as_ref())
     }
 
+    pub fn find_font_by<N: AsRef<str>>(
+        &self,
+        family: N,
+        weight: u16,
+        italic: bool,
+    ) -> Option<FontId> {
+        self.fonts.find_by(family.as_ref(), weight, italic)
+    }
+
+    pub fn font_info(&self, id: FontId) -> Option<FontInfo> {
+        self.fonts.info(id)
+    }
+
     pub fn add_fallback_fontid(&mut self, base: FontId, fallback: FontId) {
         self.fonts.add_fallback(base, fallback);
     }
@@ -1153,10 +1353,32 @@ impl<R: Renderer> Context<R> {
         self.state_mut().font_size = size;
     }
 
+    pub fn font_blur(&mut self, blur: f32) {
+        self.state_mut().font_blur = blur;
+    }
+
+    pub fn font_sdf(&mut self, sdf: bool) {
+        self.state_mut().font_sdf = sdf;
+    }
+
     pub fn text_letter_spacing(&mut self, spacing: f32) {
         self.state_mut().letter_spacing = spacing;
     }
 
+    pub fn text_word_spacing(&mut self, spacing: f32) {
+        self.state_mut().word_spacing = spacing;
+    }
+
+    pub fn text_tab_stops(&mut self, stops: &[f32]) {
+        let state = self.state_mut();
+        state.tab_stops.clear();
+        state.tab_stops.extend_from_slice(stops);
+    }
+
+    pub fn text_tab_width(&mut self, width: f32) {
+        self.state_mut().tab_width = width;
+    }
+
     pub fn text_line_height(&mut self, line_height: f32) {
         self.state_mut().line_height = line_height;
     }
@@ -1165,6 +1387,18 @@ impl<R: Renderer> Context<R> {
         self.state_mut().text_align = align;
     }
 
+    pub fn text_decoration(&mut self, decoration: TextDecoration) {
+        self.state_mut().text_decoration = decoration;
+    }
+
+    pub fn text_writing_mode(&mut self, mode: WritingMode) {
+        self.state_mut().writing_mode = mode;
+    }
+
+    pub fn text_rendering(&mut self, rendering: TextRendering) {
+        self.state_mut().text_rendering = rendering;
+    }
+
     pub fn fontid(&mut self, id: FontId) {
         self.state_mut().font_id = id;
     }
@@ -1175,85 +1409,870 @@ impl<R: Renderer> Context<R> {
         }
     }
 
+    // Sets the axis values of variable fonts, like `("wght", 650.0)`. Axes a font does not
+    // have are ignored.
+    pub fn font_variations(&mut self, variations: &[(&str, f32)]) {
+        let state = self.state_mut();
+        state.font_variations.clear();
+        state
+            .font_variations
+            .extend(variations.iter().map(|(name, value)| (tag(name), *value)));
+    }
+
+    // Turns OpenType features like `("liga", false)` or `("tnum", true)` on or off, features
+    // not listed keep the defaults of the shaper. Without the `shaping` feature only `kern` and
+    // features made of single substitutions, like `tnum` or `smcp`, are applied.
+    pub fn font_features(&mut self, features: &[(&str, bool)]) {
+        let state = self.state_mut();
+        state.font_features.clear();
+        state
+            .font_features
+            .extend(features.iter().map(|(name, enabled)| (tag(name), *enabled)));
+    }
+
     pub fn text<S: AsRef<str>, P: Into<Point>>(&mut self, pt: P, text: S) -> anyhow::Result<()> {
+        let align = self.states.last().unwrap().text_align;
+        self.render_text(pt.into(), text.as_ref(), align)
+    }
+
+    pub fn text_truncated<S: AsRef<str>, P: Into<Point>>(
+        &mut self,
+        pt: P,
+        max_width: f32,
+        text: S,
+        mode: TruncationMode,
+    ) -> anyhow::Result<TruncatedText> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let text = text.as_ref();
+
+        let truncated = self.fonts.truncate_text(
+            text,
+            self.state_font(),
+            state.font_size * scale,
+            &state.text_spacing(scale),
+            max_width * scale,
+            mode,
+        );
+
+        let align = state.text_align;
+        if truncated.is_truncated() {
+            let visible = format!(
+                "{}{}{}",
+                &text[truncated.head.clone()],
+                ELLIPSIS,
+                &text[truncated.tail.clone()]
+            );
+            self.render_text(pt.into(), &visible, align)?;
+        } else {
+            self.render_text(pt.into(), text, align)?;
+        }
+        Ok(truncated)
+    }
+
+    // Boxes are always broken into horizontal lines, the vertical writing mode is ignored.
+    pub fn text_box<S: AsRef<str>, P: Into<Point>>(
+        &mut self,
+        pt: P,
+        break_width: f32,
+        text: S,
+    ) -> anyhow::Result<()> {
+        if self.states.last().unwrap().writing_mode == WritingMode::Vertical {
+            self.save();
+            self.state_mut().writing_mode = WritingMode::Horizontal;
+            let res = self.text_box(pt, break_width, text);
+            self.restore();
+            return res;
+        }
+
         let state = self.states.last().unwrap();
         let scale = state.xform.font_scale() * self.device_pixel_ratio;
-        let xform = &state.xform;
         let invscale = 1.0 / scale;
+        let text = text.as_ref();
         let pt = pt.into();
+        let halign =
+            state.text_align & (Align::LEFT | Align::CENTER | Align::RIGHT | Align::JUSTIFY);
+        let valign =
+            state.text_align & !(Align::LEFT | Align::CENTER | Align::RIGHT | Align::JUSTIFY);
+        let line_height = self
+            .fonts
+            .text_metrics(self.state_font(), state.font_size * scale)
+            .line_height()
+            * invscale
+            * state.line_height;
+
+        let mut rows = std::mem::take(&mut self.text_rows);
+        self.break_lines(text, break_width, &mut rows);
+
+        let mut y = pt.y;
+        let res = rows.iter().try_for_each(|row| {
+            let res = match justify_spacing(halign, break_width, text, row) {
+                Some(word_spacing) => {
+                    self.save();
+                    self.state_mut().word_spacing += word_spacing;
+                    let res = self.render_text(
+                        Point::new(pt.x, y),
+                        &text[row.start..row.end],
+                        Align::LEFT | valign,
+                    );
+                    self.restore();
+                    res
+                }
+                None => self.render_text(
+                    Point::new(pt.x + row_offset(halign, break_width, row.width), y),
+                    &text[row.start..row.end],
+                    Align::LEFT | valign,
+                ),
+            };
+            y += line_height;
+            res
+        });
 
-        self.fonts.layout_text(
-            &mut self.renderer,
+        self.text_rows = rows;
+        res
+    }
+
+    pub fn text_layout<S: AsRef<str>>(&self, text: S) -> TextLayout {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let mut layout = TextLayout::new(
+            self.state_font(),
+            state.font_size * scale,
+            state.writing_mode == WritingMode::Vertical,
+        );
+        layout.scale = scale;
+        self.fonts.layout_line(
+            &mut layout,
             text.as_ref(),
-            state.font_id,
+            Default::default(),
+            state.text_align,
+            &state.text_spacing(scale),
+        );
+        layout
+    }
+
+    // Like `text_box`, the lines are horizontal in either writing mode.
+    pub fn text_box_layout<S: AsRef<str>>(&self, break_width: f32, text: S) -> TextLayout {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let text = text.as_ref();
+        let halign =
+            state.text_align & (Align::LEFT | Align::CENTER | Align::RIGHT | Align::JUSTIFY);
+        let valign =
+            state.text_align & !(Align::LEFT | Align::CENTER | Align::RIGHT | Align::JUSTIFY);
+        let line_height = self
+            .fonts
+            .text_metrics(self.state_font(), state.font_size * scale)
+            .line_height()
+            * state.line_height;
+        let mut layout = TextLayout::new(self.state_font(), state.font_size * scale, false);
+        layout.scale = scale;
+
+        let mut rows = Vec::new();
+        self.break_lines(text, break_width, &mut rows);
+
+        let mut y = 0.0;
+        for row in &rows {
+            let (x, spacing) = match justify_spacing(halign, break_width, text, row) {
+                Some(word_spacing) => {
+                    let mut state = state.clone();
+                    state.word_spacing += word_spacing;
+                    (0.0, state.text_spacing(scale))
+                }
+                None => (
+                    row_offset(halign, break_width, row.width),
+                    state.text_spacing(scale),
+                ),
+            };
+            self.fonts.layout_line(
+                &mut layout,
+                &text[row.start..row.end],
+                Point::new(x * scale, y),
+                Align::LEFT | valign,
+                &spacing,
+            );
+            y += line_height;
+        }
+        layout.extent.height = y;
+        layout
+    }
+
+    pub fn draw_text_layout<P: Into<Point>>(
+        &mut self,
+        pt: P,
+        layout: &TextLayout,
+    ) -> anyhow::Result<()> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        self.render_layout(pt.into(), layout, scale / layout.scale)
+    }
+
+    pub fn text_bounds<S: AsRef<str>, P: Into<Point>>(&self, pt: P, text: S) -> (f32, Bounds) {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let invscale = 1.0 / scale;
+        let pt = pt.into();
+
+        let (advance, bounds) = self.fonts.text_bounds(
+            text.as_ref(),
+            self.state_font(),
             (pt.x * scale, pt.y * scale).into(),
             state.font_size * scale,
             state.text_align,
-            state.letter_spacing * scale,
-            true,
-            &mut self.layout_chars,
-        )?;
+            &state.text_spacing(scale),
+        );
 
-        self.cache.vertexes.clear();
+        (
+            advance * invscale,
+            Bounds {
+                min: Point::new(bounds.min.x * invscale, bounds.min.y * invscale),
+                max: Point::new(bounds.max.x * invscale, bounds.max.y * invscale),
+            },
+        )
+    }
 
-        for lc in &self.layout_chars {
-            let lt = xform.transform_point(Point::new(
-                lc.bounds.min.x * invscale,
-                lc.bounds.min.y * invscale,
-            ));
-            let rt = xform.transform_point(Point::new(
-                lc.bounds.max.x * invscale,
-                lc.bounds.min.y * invscale,
-            ));
-            let lb = xform.transform_point(Point::new(
-                lc.bounds.min.x * invscale,
-                lc.bounds.max.y * invscale,
-            ));
-            let rb = xform.transform_point(Point::new(
-                lc.bounds.max.x * invscale,
-                lc.bounds.max.y * invscale,
-            ));
+    pub fn text_box_bounds<S: AsRef<str>, P: Into<Point>>(
+        &self,
+        pt: P,
+        break_width: f32,
+        text: S,
+    ) -> Bounds {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let invscale = 1.0 / scale;
+        let pt = pt.into();
+        let halign =
+            state.text_align & (Align::LEFT | Align::CENTER | Align::RIGHT | Align::JUSTIFY);
+        let valign =
+            state.text_align & !(Align::LEFT | Align::CENTER | Align::RIGHT | Align::JUSTIFY);
+        let line_height = self
+            .fonts
+            .text_metrics(self.state_font(), state.font_size * scale)
+            .line_height()
+            * invscale
+            * state.line_height;
+        let (row_min_y, row_max_y) =
+            self.fonts
+                .line_bounds(self.state_font(), state.font_size * scale, 0.0, valign);
+
+        let mut rows = Vec::new();
+        self.break_lines(text.as_ref(), break_width, &mut rows);
+
+        let mut bounds = Bounds { min: pt, max: pt };
+        let mut y = pt.y;
+        for row in &rows {
+            let x = pt.x + row_offset(halign, break_width, row.width);
+            // Justified rows are stretched to the full width.
+            let max_x = match justify_spacing(halign, break_width, text.as_ref(), row) {
+                Some(_) => row.max_x + break_width - row.width,
+                None => row.max_x,
+            };
+            bounds.min.x = bounds.min.x.min(x + row.min_x);
+            bounds.max.x = bounds.max.x.max(x + max_x);
+            bounds.min.y = bounds.min.y.min(y + row_min_y * invscale);
+            bounds.max.y = bounds.max.y.max(y + row_max_y * invscale);
+            y += line_height;
+        }
+        bounds
+    }
 
-            self.cache
-                .vertexes
-                .push(Vertex::new(lt.x, lt.y, lc.uv.min.x, lc.uv.min.y));
-            self.cache
-                .vertexes
-                .push(Vertex::new(rb.x, rb.y, lc.uv.max.x, lc.uv.max.y));
-            self.cache
-                .vertexes
-                .push(Vertex::new(rt.x, rt.y, lc.uv.max.x, lc.uv.min.y));
+    pub fn text_break_lines<S: AsRef<str>>(&self, text: S, break_width: f32) -> Vec<TextRow> {
+        let mut rows = Vec::new();
+        self.break_lines(text.as_ref(), break_width, &mut rows);
+        rows
+    }
 
-            self.cache
-                .vertexes
-                .push(Vertex::new(lt.x, lt.y, lc.uv.min.x, lc.uv.min.y));
-            self.cache
-                .vertexes
-                .push(Vertex::new(lb.x, lb.y, lc.uv.min.x, lc.uv.max.y));
-            self.cache
-                .vertexes
-                .push(Vertex::new(rb.x, rb.y, lc.uv.max.x, lc.uv.max.y));
+    pub fn text_path<S: AsRef<str>, P: Into<Point>>(&mut self, pt: P, text: S) {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let invscale = 1.0 / scale;
+        let pt = pt.into();
+
+        let glyphs = self.fonts.text_outlines(
+            text.as_ref(),
+            self.state_font(),
+            (pt.x * scale, pt.y * scale).into(),
+            state.font_size * scale,
+            state.text_align,
+            &state.text_spacing(scale),
+        );
+
+        let xform = state.xform;
+        let area = |contour: &Contour| {
+            let points: Vec<Point> = contour
+                .iter()
+                .flat_map(|segment| match *segment {
+                    Segment::Line(p0, _) => vec![p0],
+                    Segment::Quad(p0, p1, _) => vec![p0, p1],
+                    Segment::Cubic(p0, p1, p2, _) => vec![p0, p1, p2],
+                })
+                .map(|pt| xform.transform_point(pt))
+                .collect();
+            let mut area = 0.0;
+            for i in 2..points.len() {
+                let (a, b, c) = (points[0], points[i - 1], points[i]);
+                area += (c.x - a.x) * (b.y - a.y) - (b.x - a.x) * (c.y - a.y);
+            }
+            area
+        };
+
+        for contours in glyphs {
+            // The largest contour is an outer one, contours wound the other way are holes.
+            let areas: Vec<f32> = contours.iter().map(area).collect();
+            let outer = areas
+                .iter()
+                .copied()
+                .fold(0.0f32, |a, b| if b.abs() > a.abs() { b } else { a });
+
+            for (contour, area) in contours.iter().zip(areas) {
+                let pt = match contour.first() {
+                    Some(Segment::Line(p0, _))
+                    | Some(Segment::Quad(p0, _, _))
+                    | Some(Segment::Cubic(p0, _, _, _)) => *p0,
+                    None => continue,
+                };
+
+                self.move_to((pt.x * invscale, pt.y * invscale));
+                for segment in contour {
+                    match *segment {
+                        Segment::Line(_, p1) => self.line_to((p1.x * invscale, p1.y * invscale)),
+                        Segment::Quad(_, p1, p2) => self.quad_to(
+                            (p1.x * invscale, p1.y * invscale),
+                            (p2.x * invscale, p2.y * invscale),
+                        ),
+                        Segment::Cubic(_, p1, p2, p3) => self.bezier_to(
+                            (p1.x * invscale, p1.y * invscale),
+                            (p2.x * invscale, p2.y * invscale),
+                            (p3.x * invscale, p3.y * invscale),
+                        ),
+                    }
+                }
+                self.close_path();
+                self.path_solidity(if (area < 0.0) == (outer < 0.0) {
+                    Solidity::Solid
+                } else {
+                    Solidity::Hole
+                });
+            }
         }
+    }
 
-        let mut paint = state.fill.clone();
-        paint.image = Some(self.fonts.img.clone());
-        paint.inner_color.a *= state.alpha;
-        paint.outer_color.a *= state.alpha;
+    pub fn text_stroke<S: AsRef<str>, P: Into<Point>>(
+        &mut self,
+        pt: P,
+        text: S,
+    ) -> anyhow::Result<()> {
+        let pt = pt.into();
+        self.with_separate_path(|ctx| {
+            ctx.text_path(pt, text);
+            ctx.stroke()
+        })
+    }
 
-        self.renderer.triangles(
-            &paint,
-            state.composite_operation,
-            &state.scissor,
-            &self.cache.vertexes,
+    pub fn text_glyph_positions<S: AsRef<str>, P: Into<Point>>(
+        &self,
+        pt: P,
+        text: S,
+    ) -> Vec<GlyphPosition> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let invscale = 1.0 / scale;
+        let pt = pt.into();
+
+        let mut positions = self.fonts.glyph_positions(
+            text.as_ref(),
+            self.state_font(),
+            (pt.x * scale, pt.y * scale).into(),
+            state.font_size * scale,
+            state.text_align,
+            &state.text_spacing(scale),
+        );
+
+        for pos in &mut positions {
+            pos.x *= invscale;
+            pos.next_x *= invscale;
+            pos.min_x *= invscale;
+            pos.max_x *= invscale;
+        }
+        positions
+    }
+
+    pub fn text_hit_test<S: AsRef<str>, P1: Into<Point>, P2: Into<Point>>(
+        &self,
+        pt: P1,
+        text: S,
+        hit: P2,
+    ) -> usize {
+        let text = text.as_ref();
+        let positions = self.text_glyph_positions(pt, text);
+        hit_test_positions(&positions, text.len(), hit.into().x)
+    }
+
+    pub fn text_style(&self) -> TextStyle {
+        let state = self.states.last().unwrap();
+        TextStyle {
+            font_id: state.font_id,
+            font_size: state.font_size,
+            fill: state.fill,
+            letter_spacing: state.letter_spacing,
+        }
+    }
+
+    pub fn rich_text<P: Into<Point>>(
+        &mut self,
+        pt: P,
+        break_width: f32,
+        text: &RichText,
+    ) -> anyhow::Result<()> {
+        for (origin, row) in self.layout_rich_text(pt.into(), break_width, text) {
+            let mut x = origin.x;
+            for (n, _, segment) in text.segments(row.start, row.end) {
+                let style = &text.spans[n].style;
+                self.save();
+                let state = self.states.last_mut().unwrap();
+                state.font_id = style.font_id;
+                state.font_size = style.font_size;
+                state.fill = style.fill;
+                state.letter_spacing = style.letter_spacing;
+                state.word_spacing = 0.0;
+                state.tab_stops.clear();
+                state.tab_width = 0.0;
+                let res = self.render_text(
+                    Point::new(x, origin.y),
+                    segment,
+                    Align::LEFT | Align::BASELINE,
+                );
+                self.restore();
+                res?;
+                x += self.segment_advance(style, segment);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rich_text_bounds<P: Into<Point>>(
+        &self,
+        pt: P,
+        break_width: f32,
+        text: &RichText,
+    ) -> Bounds {
+        let pt = pt.into();
+        let mut bounds = Bounds { min: pt, max: pt };
+        for (origin, row) in self.layout_rich_text(pt, break_width, text) {
+            bounds.min.x = bounds.min.x.min(origin.x + row.min_x);
+            bounds.max.x = bounds.max.x.max(origin.x + row.max_x);
+            bounds.min.y = bounds.min.y.min(origin.y - row.ascender);
+            bounds.max.y = bounds.max.y.max(origin.y - row.descender);
+        }
+        bounds
+    }
+
+    pub fn rich_text_break_lines(&self, break_width: f32, text: &RichText) -> Vec<RichTextRow> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let invscale = 1.0 / scale;
+
+        let mut rows = Vec::new();
+        self.fonts.break_rich_text(
+            text,
+            scale,
+            &state.font_variations,
+            |style| state.span_spacing(style.letter_spacing, scale),
+            break_width * scale,
+            &mut rows,
+        );
+
+        for row in &mut rows {
+            row.width *= invscale;
+            row.min_x *= invscale;
+            row.max_x *= invscale;
+            row.ascender *= invscale;
+            row.descender *= invscale;
+            row.height *= invscale;
+        }
+        rows
+    }
+
+    pub fn rich_text_hit_test<P1: Into<Point>, P2: Into<Point>>(
+        &self,
+        pt: P1,
+        break_width: f32,
+        text: &RichText,
+        hit: P2,
+    ) -> TextOffset {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let invscale = 1.0 / scale;
+        let hit = hit.into();
+
+        let rows = self.layout_rich_text(pt.into(), break_width, text);
+        let (origin, row) = match rows
+            .iter()
+            .find(|(origin, row)| hit.y < origin.y - row.ascender + row.height * state.line_height)
+            .or_else(|| rows.last())
+        {
+            Some(row) => *row,
+            None => return Default::default(),
+        };
+
+        let mut x = origin.x;
+        let mut segments = text.segments(row.start, row.end).peekable();
+        while let Some((n, from, segment)) = segments.next() {
+            let style = &text.spans[n].style;
+            let advance = self.segment_advance(style, segment);
+            if hit.x < x + advance || segments.peek().is_none() {
+                let mut positions = self.fonts.glyph_positions(
+                    segment,
+                    self.fonts.instance(style.font_id, &state.font_variations),
+                    (x * scale, 0.0).into(),
+                    style.font_size * scale,
+                    Align::LEFT | Align::BASELINE,
+                    &state.span_spacing(style.letter_spacing, scale),
+                );
+                for pos in &mut positions {
+                    pos.x *= invscale;
+                    pos.next_x *= invscale;
+                }
+                return TextOffset {
+                    span: n,
+                    idx: from + hit_test_positions(&positions, segment.len(), hit.x),
+                };
+            }
+            x += advance;
+        }
+        row.start
+    }
+
+    fn layout_rich_text(
+        &self,
+        pt: Point,
+        break_width: f32,
+        text: &RichText,
+    ) -> Vec<(Point, RichTextRow)> {
+        let state = self.states.last().unwrap();
+        let halign = state.text_align & (Align::LEFT | Align::CENTER | Align::RIGHT);
+        let rows = self.rich_text_break_lines(break_width, text);
+
+        // Every row sits on a shared baseline below the tallest span of the row, the first
+        // baseline is placed by the vertical alignment.
+        let mut y = match rows.first() {
+            Some(row) if state.text_align.contains(Align::TOP) => pt.y + row.ascender,
+            Some(row) if state.text_align.contains(Align::MIDDLE) => {
+                pt.y + (row.ascender + row.descender) * 0.5
+            }
+            Some(row) if state.text_align.contains(Align::BOTTOM) => pt.y + row.descender,
+            _ => pt.y,
+        };
+
+        let mut result = Vec::with_capacity(rows.len());
+        for (n, row) in rows.iter().enumerate() {
+            if n > 0 {
+                let prev = &rows[n - 1];
+                y += prev.height * state.line_height - prev.ascender + row.ascender;
+            }
+            let x = pt.x + row_offset(halign, break_width, row.width);
+            result.push((Point::new(x, y), *row));
+        }
+        result
+    }
+
+    fn segment_advance(&self, style: &TextStyle, segment: &str) -> f32 {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        self.fonts
+            .text_size(
+                segment,
+                self.fonts.instance(style.font_id, &state.font_variations),
+                style.font_size * scale,
+                &state.span_spacing(style.letter_spacing, scale),
+            )
+            .width
+            / scale
+    }
+
+    fn break_lines(&self, text: &str, break_width: f32, rows: &mut Vec<TextRow>) {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let invscale = 1.0 / scale;
+
+        self.fonts.break_lines(
+            text,
+            self.state_font(),
+            state.font_size * scale,
+            &state.text_spacing(scale),
+            break_width * scale,
+            rows,
+        );
+
+        for row in rows.iter_mut() {
+            row.width *= invscale;
+            row.min_x *= invscale;
+            row.max_x *= invscale;
+        }
+    }
+
+    // Layouts are cached without the transform scale, so that zooming text reuses them.
+    fn render_text(&mut self, pt: Point, text: &str, align: Align) -> anyhow::Result<()> {
+        let state = self.states.last().unwrap();
+        let scale = self.device_pixel_ratio;
+        let layout = self.fonts.cached_layout(
+            text,
+            self.state_font(),
+            state.font_size * scale,
+            align,
+            &state.text_spacing(scale),
+            state.writing_mode == WritingMode::Vertical,
+        );
+        self.render_layout(pt, &layout, state.xform.font_scale())
+    }
+
+    // Layouts made at another scale are scaled by `layout_scale` to the current one.
+    fn render_layout(
+        &mut self,
+        pt: Point,
+        layout: &TextLayout,
+        layout_scale: f32,
+    ) -> anyhow::Result<()> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let xform = &state.xform;
+        let invscale = 1.0 / scale;
+        // Snapped text is laid out on the device pixel grid and moved back when drawn.
+        let grid = state.pixel_grid(self.device_pixel_ratio);
+        let offset = grid.unwrap_or_default();
+        let hinted = grid.is_some() && state.text_rendering == TextRendering::Hinted;
+        // LCD glyphs fall back to grayscale where the subpixels would not line up.
+        let kind = if state.font_sdf {
+            AtlasKind::DistanceField
+        } else if state.text_rendering == TextRendering::Lcd
+            && grid.is_some()
+            && state.font_blur * scale < 0.5
+            && !layout.vertical
+        {
+            AtlasKind::Lcd
+        } else {
+            AtlasKind::Coverage
+        };
+
+        self.fonts.place_layout(
+            layout,
+            (pt.x * scale + offset.x, pt.y * scale + offset.y).into(),
+            layout_scale,
+            grid.is_some(),
+            hinted,
+            &mut self.layout_chars,
+        );
+        let (img, color_img) = self.fonts.rasterize_glyphs(
+            &mut self.renderer,
+            state.font_blur * scale,
+            kind,
+            &mut self.layout_chars,
         )?;
+
+        for (color, img) in [(false, img), (true, color_img)].iter().copied() {
+            let img = match img {
+                Some(img) => img,
+                None => continue,
+            };
+
+            self.cache.vertexes.clear();
+
+            for lc in self.layout_chars.iter().filter(|lc| lc.color == color) {
+                // Sideways glyphs of vertical text are laid out in a frame rotated by 90 degrees.
+                let corner = |x: f32, y: f32| {
+                    let (x, y) = if lc.rotated { (-y, x) } else { (x, y) };
+                    xform.transform_point(Point::new(
+                        (x - offset.x) * invscale,
+                        (y - offset.y) * invscale,
+                    ))
+                };
+                let lt = corner(lc.bounds.min.x, lc.bounds.min.y);
+                let rt = corner(lc.bounds.max.x, lc.bounds.min.y);
+                let lb = corner(lc.bounds.min.x, lc.bounds.max.y);
+                let rb = corner(lc.bounds.max.x, lc.bounds.max.y);
+
+                self.cache
+                    .vertexes
+                    .push(Vertex::new(lt.x, lt.y, lc.uv.min.x, lc.uv.min.y));
+                self.cache
+                    .vertexes
+                    .push(Vertex::new(rb.x, rb.y, lc.uv.max.x, lc.uv.max.y));
+                self.cache
+                    .vertexes
+                    .push(Vertex::new(rt.x, rt.y, lc.uv.max.x, lc.uv.min.y));
+
+                self.cache
+                    .vertexes
+                    .push(Vertex::new(lt.x, lt.y, lc.uv.min.x, lc.uv.min.y));
+                self.cache
+                    .vertexes
+                    .push(Vertex::new(lb.x, lb.y, lc.uv.min.x, lc.uv.max.y));
+                self.cache
+                    .vertexes
+                    .push(Vertex::new(rb.x, rb.y, lc.uv.max.x, lc.uv.max.y));
+            }
+
+            let mut paint = state.fill.clone();
+            paint.inner_color.a *= state.alpha;
+            paint.outer_color.a *= state.alpha;
+
+            if color {
+                // Color glyphs keep their own colors and only take the global alpha.
+                paint.inner_color = Color::rgba(1.0, 1.0, 1.0, state.alpha);
+                paint.image = Some(img);
+                self.renderer.triangles(
+                    &paint,
+                    state.composite_operation,
+                    &state.scissor,
+                    &self.cache.vertexes,
+                )?;
+            } else {
+                self.renderer.text(
+                    &paint,
+                    state.composite_operation,
+                    &state.scissor,
+                    img,
+                    &self.cache.vertexes,
+                )?;
+            }
+        }
+
+        if !state.text_decoration.is_empty() && !layout.vertical {
+            self.render_decorations(pt, layout, layout_scale)?;
+        }
         Ok(())
     }
 
+    fn render_decorations(
+        &mut self,
+        pt: Point,
+        layout: &TextLayout,
+        layout_scale: f32,
+    ) -> anyhow::Result<()> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let invscale = 1.0 / scale;
+        let metrics = self
+            .fonts
+            .text_metrics(layout.font_id, layout.size * layout_scale);
+
+        let mut lines = Vec::new();
+        for (x, baseline, width) in layout.lines.iter().copied() {
+            let x = pt.x * scale + x * layout_scale;
+            let baseline = pt.y * scale + baseline * layout_scale;
+            let width = width * layout_scale;
+            if state.text_decoration.contains(TextDecoration::OVERLINE) {
+                lines.push((
+                    x,
+                    width,
+                    baseline - metrics.ascender,
+                    metrics.underline_thickness,
+                    false,
+                ));
+            }
+            if state.text_decoration.contains(TextDecoration::UNDERLINE) {
+                lines.push((
+                    x,
+                    width,
+                    baseline - metrics.underline_position,
+                    metrics.underline_thickness,
+                    state.text_decoration.contains(TextDecoration::SKIP_INK),
+                ));
+            }
+            if state.text_decoration.contains(TextDecoration::LINE_THROUGH) {
+                lines.push((
+                    x,
+                    width,
+                    baseline - metrics.strikeout_position,
+                    metrics.strikeout_thickness,
+                    false,
+                ));
+            }
+        }
+
+        let mut rects = Vec::new();
+        let grid = state.pixel_grid(self.device_pixel_ratio);
+        let offset = grid.unwrap_or_default();
+
+        for (x, width, top, thickness, skip_ink) in lines {
+            let (top, thickness) = match grid {
+                Some(grid) => ((top + grid.y).round() - grid.y, thickness.round().max(1.0)),
+                None => (top, thickness),
+            };
+            let mut gaps = Vec::new();
+            if skip_ink {
+                // Leave a gap around the descenders crossing the line.
+                gaps.extend(
+                    self.layout_chars
+                        .iter()
+                        .filter_map(|lc| {
+                            self.fonts
+                                .ink_extent(lc, top + offset.y, top + thickness + offset.y)
+                        })
+                        .map(|(min_x, max_x)| {
+                            (min_x - offset.x - thickness, max_x - offset.x + thickness)
+                        }),
+                );
+                gaps.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            }
+
+            let mut start = x;
+            for (min_x, max_x) in gaps.into_iter().chain(Some((x + width, x + width))) {
+                if min_x > start {
+                    rects.push((start, top, min_x.min(x + width), top + thickness));
+                }
+                start = start.max(max_x);
+            }
+        }
+
+        self.with_separate_path(|ctx| {
+            for (x0, y0, x1, y1) in rects {
+                ctx.move_to((x0 * invscale, y0 * invscale));
+                ctx.line_to((x0 * invscale, y1 * invscale));
+                ctx.line_to((x1 * invscale, y1 * invscale));
+                ctx.line_to((x1 * invscale, y0 * invscale));
+                ctx.close_path();
+            }
+            ctx.fill()
+        })
+    }
+
+    // Runs `f` on an empty path, the current path is restored afterwards.
+    fn with_separate_path<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let mut commands = std::mem::take(&mut self.path_commands);
+        commands.clear();
+        std::mem::swap(&mut self.commands, &mut commands);
+        std::mem::swap(&mut self.cache, &mut self.path_cache);
+        self.cache.clear();
+        let last_position = self.last_position;
+
+        let res = f(self);
+
+        self.last_position = last_position;
+        std::mem::swap(&mut self.cache, &mut self.path_cache);
+        std::mem::swap(&mut self.commands, &mut commands);
+        self.path_commands = commands;
+        res
+    }
+
     pub fn text_metrics(&self) -> TextMetrics {
         let state = self.states.last().unwrap();
         let scale = state.xform.font_scale() * self.device_pixel_ratio;
-        self.fonts
-            .text_metrics(state.font_id, state.font_size * scale)
+        let invscale = 1.0 / scale;
+        let metrics = self
+            .fonts
+            .text_metrics(self.state_font(), state.font_size * scale);
+        TextMetrics {
+            ascender: metrics.ascender * invscale,
+            descender: metrics.descender * invscale,
+            line_gap: metrics.line_gap * invscale,
+            x_height: metrics.x_height * invscale,
+            cap_height: metrics.cap_height * invscale,
+            underline_position: metrics.underline_position * invscale,
+            underline_thickness: metrics.underline_thickness * invscale,
+            strikeout_position: metrics.strikeout_position * invscale,
+            strikeout_thickness: metrics.strikeout_thickness * invscale,
+        }
     }
 
     pub fn text_size<S: AsRef<str>>(&self, text: S) -> Extent {
@@ -1261,9 +2280,153 @@ impl<R: Renderer> Context<R> {
         let scale = state.xform.font_scale() * self.device_pixel_ratio;
         self.fonts.text_size(
             text.as_ref(),
-            state.font_id,
+            self.state_font(),
             state.font_size * scale,
-            state.letter_spacing * scale,
+            &state.text_spacing(scale),
         )
     }
 }
+
+// Tags shorter than four characters are padded with spaces.
+fn tag(name: &str) -> Tag {
+    let mut tag = *b"    ";
+    for (t, b) in tag.iter_mut().zip(name.bytes()) {
+        *t = b;
+    }
+    tag
+}
+
+// Positions are in visual order, right-to-left glyphs start on their right edge.
+fn hit_test_positions(positions: &[GlyphPosition], len: usize, x: f32) -> usize {
+    let cluster_end = |idx: usize| {
+        positions
+            .iter()
+            .map(|pos| pos.idx)
+            .filter(|next| *next > idx)
+            .min()
+            .unwrap_or(len)
+    };
+
+    for pos in positions {
+        if x < (pos.x + pos.next_x) * 0.5 {
+            return if pos.rtl {
+                cluster_end(pos.idx)
+            } else {
+                pos.idx
+            };
+        } else if pos.rtl && x < pos.next_x {
+            return pos.idx;
+        }
+    }
+
+    match positions.last() {
+        Some(pos) if pos.rtl => pos.idx,
+        Some(pos) => cluster_end(pos.idx),
+        None => len,
+    }
+}
+
+// The extra space added to every space of a justified row, the last row of a paragraph keeps
+// its natural width.
+fn justify_spacing(halign: Align, break_width: f32, text: &str, row: &TextRow) -> Option<f32> {
+    if !halign.contains(Align::JUSTIFY)
+        || row.next >= text.len()
+        || text[row.end..row.next].chars().any(is_newline)
+    {
+        return None;
+    }
+    let spaces = text[row.start..row.end].matches(' ').count();
+    if spaces > 0 && row.width < break_width {
+        Some((break_width - row.width) / spaces as f32)
+    } else {
+        None
+    }
+}
+
+fn row_offset(halign: Align, break_width: f32, row_width: f32) -> f32 {
+    if halign.contains(Align::CENTER) {
+        break_width * 0.5 - row_width * 0.5
+    } else if halign.contains(Align::RIGHT) {
+        break_width - row_width
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(idx: usize, x: f32, rtl: bool) -> GlyphPosition {
+        GlyphPosition {
+            idx,
+            x,
+            next_x: x + 10.0,
+            min_x: x,
+            max_x: x + 10.0,
+            rtl,
+        }
+    }
+
+    #[test]
+    fn hit_test_positions_in_rtl_runs() {
+        // "ab" followed by the right-to-left "\u{5d0}\u{5d1}", which is drawn reversed.
+        let positions = [
+            position(0, 0.0, false),
+            position(1, 10.0, false),
+            position(4, 20.0, true),
+            position(2, 30.0, true),
+        ];
+        assert_eq!(hit_test_positions(&positions, 6, 4.0), 0);
+        assert_eq!(hit_test_positions(&positions, 6, 14.0), 1);
+        assert_eq!(hit_test_positions(&positions, 6, 22.0), 6);
+        assert_eq!(hit_test_positions(&positions, 6, 28.0), 4);
+        assert_eq!(hit_test_positions(&positions, 6, 32.0), 4);
+        assert_eq!(hit_test_positions(&positions, 6, 38.0), 2);
+        assert_eq!(hit_test_positions(&positions, 6, 45.0), 2);
+    }
+
+    #[test]
+    fn hit_test_positions_past_ltr_text() {
+        let positions = [position(0, 0.0, false), position(1, 10.0, false)];
+        assert_eq!(hit_test_positions(&positions, 2, 25.0), 2);
+        assert_eq!(hit_test_positions(&[], 0, 5.0), 0);
+    }
+
+    fn row(start: usize, end: usize, next: usize, width: f32) -> TextRow {
+        TextRow {
+            start,
+            end,
+            next,
+            width,
+            min_x: 0.0,
+            max_x: width,
+        }
+    }
+
+    #[test]
+    fn justify_spacing_spreads_over_spaces() {
+        let text = "aa bb cc dd\nee";
+        let first = row(0, 5, 6, 50.0);
+        assert_eq!(
+            justify_spacing(Align::JUSTIFY, 80.0, text, &first),
+            Some(30.0)
+        );
+        assert_eq!(justify_spacing(Align::LEFT, 80.0, text, &first), None);
+        assert_eq!(justify_spacing(Align::JUSTIFY, 40.0, text, &first), None);
+
+        // Rows ending a paragraph and rows without spaces keep their width.
+        assert_eq!(
+            justify_spacing(Align::JUSTIFY, 80.0, text, &row(6, 11, 12, 50.0)),
+            None
+        );
+        assert_eq!(
+            justify_spacing(Align::JUSTIFY, 80.0, text, &row(12, 14, 14, 20.0)),
+            None
+        );
+        assert_eq!(
+            justify_spacing(Align::JUSTIFY, 80.0, "aaaa bb", &row(0, 4, 5, 40.0)),
+            None
+        );
+    }
+}
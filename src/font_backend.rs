@@ -0,0 +1,91 @@
+use crate::Point;
+use std::sync::Arc;
+
+pub type GlyphId = u16;
+
+// An OpenType tag like `wght` or `liga`, shorter names are padded with spaces.
+pub type Tag = [u8; 4];
+
+// The vertical metrics of a face in font units, the descent is below the baseline and negative.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FaceMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+// Pixels per font unit, the horizontal scale differs for subpixel rendering.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GlyphScale {
+    pub x: f32,
+    pub y: f32,
+}
+
+// The pixels covered by a glyph, y grows downwards.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PixelBounds {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+impl PixelBounds {
+    pub fn width(&self) -> usize {
+        (self.max_x - self.min_x) as usize
+    }
+
+    pub fn height(&self) -> usize {
+        (self.max_y - self.min_y) as usize
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Segment {
+    Line(Point, Point),
+    Quad(Point, Point, Point),
+    Cubic(Point, Point, Point, Point),
+}
+
+pub type Contour = Vec<Segment>;
+
+pub trait FontFace: Send {
+    fn metrics(&self) -> FaceMetrics;
+
+    // `None` if the face has no glyph for the character.
+    fn glyph_index(&self, c: char) -> Option<GlyphId>;
+
+    fn advance(&self, glyph: GlyphId) -> f32;
+
+    fn kerning(&self, left: GlyphId, right: GlyphId) -> f32;
+
+    // The glyph scaled and placed with its origin at `position`, `None` for empty glyphs.
+    fn glyph_bounds(
+        &self,
+        glyph: GlyphId,
+        scale: GlyphScale,
+        position: Point,
+    ) -> Option<PixelBounds>;
+
+    // Writes the coverage of the pixels within `glyph_bounds` to `coverage`, the first pixel
+    // of each row is `stride` bytes after the one of the previous row.
+    fn rasterize(
+        &self,
+        glyph: GlyphId,
+        scale: GlyphScale,
+        position: Point,
+        coverage: &mut [u8],
+        stride: usize,
+    );
+
+    // The contours in pixels, placed like in `glyph_bounds`.
+    fn outline(&self, glyph: GlyphId, scale: GlyphScale, position: Point) -> Vec<Contour>;
+
+    // Selects the instance of a variable font, in user axis values. Faces of backends without
+    // variation support keep the default instance.
+    fn set_variations(&mut self, _variations: &[(Tag, f32)]) {}
+}
+
+pub trait FontBackend: Send {
+    fn load_face(&self, data: Arc<[u8]>, index: u32) -> anyhow::Result<Box<dyn FontFace>>;
+}
@@ -0,0 +1,170 @@
+use crate::fonts::FontInfo;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+pub(crate) struct SystemFont {
+    pub(crate) path: PathBuf,
+    pub(crate) index: u32,
+    pub(crate) info: FontInfo,
+    coverage: Vec<(u32, u32)>,
+}
+
+impl SystemFont {
+    fn read(path: &Path, data: &[u8], index: u32) -> Option<SystemFont> {
+        let face = ttf_parser::Face::from_slice(data, index).ok()?;
+        let mut codepoints = Vec::new();
+
+        for subtable in face.tables().cmap?.subtables {
+            if subtable.is_unicode() {
+                subtable.codepoints(|c| codepoints.push(c));
+            }
+        }
+        codepoints.sort_unstable();
+        codepoints.dedup();
+
+        let mut coverage: Vec<(u32, u32)> = Vec::new();
+        for c in codepoints {
+            match coverage.last_mut() {
+                Some((_, end)) if *end + 1 == c => *end = c,
+                _ => coverage.push((c, c)),
+            }
+        }
+
+        Some(SystemFont {
+            path: path.to_path_buf(),
+            index,
+            info: FontInfo::read(data, index).ok()?,
+            coverage,
+        })
+    }
+
+    pub fn covers(&self, c: char) -> bool {
+        let c = c as u32;
+        self.coverage
+            .binary_search_by(|(start, end)| {
+                if *end < c {
+                    std::cmp::Ordering::Less
+                } else if *start > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+}
+
+pub(crate) fn standard_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if cfg!(target_os = "windows") {
+        if let Some(windir) = std::env::var_os("WINDIR") {
+            dirs.push(Path::new(&windir).join("Fonts"));
+        }
+        if let Some(local) = std::env::var_os("LOCALAPPDATA") {
+            dirs.push(Path::new(&local).join("Microsoft\\Windows\\Fonts"));
+        }
+    } else if cfg!(target_os = "macos") {
+        dirs.push("/System/Library/Fonts".into());
+        dirs.push("/Library/Fonts".into());
+        if let Some(home) = std::env::var_os("HOME") {
+            dirs.push(Path::new(&home).join("Library/Fonts"));
+        }
+    } else {
+        dirs.push("/usr/share/fonts".into());
+        dirs.push("/usr/local/share/fonts".into());
+        if let Some(data_home) = std::env::var_os("XDG_DATA_HOME") {
+            dirs.push(Path::new(&data_home).join("fonts"));
+        } else if let Some(home) = std::env::var_os("HOME") {
+            dirs.push(Path::new(&home).join(".local/share/fonts"));
+        }
+        if let Some(home) = std::env::var_os("HOME") {
+            dirs.push(Path::new(&home).join(".fonts"));
+        }
+    }
+
+    dirs
+}
+
+// Paths are canonical, so directories linked into themselves are scanned once and fonts
+// already in `result` are not added again.
+pub(crate) fn scan_dir(dir: &Path, result: &mut Vec<SystemFont>) {
+    let mut known = result.iter().map(|font| font.path.clone()).collect();
+    scan_fonts(dir, &mut HashSet::new(), &mut known, result);
+}
+
+fn scan_fonts(
+    dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+    known: &mut HashSet<PathBuf>,
+    result: &mut Vec<SystemFont>,
+) {
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    if !visited.insert(dir.clone()) {
+        return;
+    }
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = match entry.path().canonicalize() {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        if path.is_dir() {
+            scan_fonts(&path, visited, known, result);
+            continue;
+        }
+
+        let is_font = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| {
+                matches!(
+                    ext.to_ascii_lowercase().as_str(),
+                    "ttf" | "otf" | "ttc" | "otc"
+                )
+            })
+            .unwrap_or_default();
+        if !is_font || !known.insert(path.clone()) {
+            continue;
+        }
+
+        if let Ok(data) = std::fs::read(&path) {
+            for index in 0..ttf_parser::fonts_in_collection(&data).unwrap_or(1) {
+                if let Some(font) = SystemFont::read(&path, &data, index) {
+                    result.push(font);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_dir_skips_cycles_and_known_fonts() {
+        let dir = std::env::temp_dir().join(format!("nvg-fontdb-{}", std::process::id()));
+        let fonts = dir.join("fonts");
+        std::fs::create_dir_all(&fonts).unwrap();
+        std::fs::write(
+            fonts.join("Roboto-Bold.ttf"),
+            include_bytes!("../nvg-gl/examples/Roboto-Bold.ttf").as_ref(),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(&dir, fonts.join("loop")).unwrap();
+
+        let mut result = Vec::new();
+        scan_dir(&dir, &mut result);
+        scan_dir(&fonts, &mut result);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.len(), 1);
+    }
+}
@@ -1,17 +1,63 @@
+use crate::atlas::Atlas;
+use crate::colr::ColorLayers;
 use crate::context::{ImageId, TextMetrics};
+use crate::font_backend::{
+    Contour, FaceMetrics, FontBackend, FontFace, GlyphId, GlyphScale, PixelBounds, Tag,
+};
+#[cfg(feature = "system-fonts")]
+use crate::fontdb::{self, SystemFont};
 use crate::renderer::TextureType;
+use crate::rich_text::{RichText, RichTextRow, TextOffset, TextStyle};
 use crate::{Align, Bounds, Extent, ImageFlags, Renderer};
 use bitflags::_core::borrow::Borrow;
-use rusttype::gpu_cache::Cache;
-use rusttype::{Font, Glyph, Point, PositionedGlyph, Scale};
-use slab::Slab;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+#[cfg(feature = "system-fonts")]
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::sync::Arc;
+use unicode_bidi::BidiInfo;
+use unicode_linebreak::BreakOpportunity;
 
-const TEX_WIDTH: usize = 1024;
-const TEX_HEIGHT: usize = 1024;
+#[cfg(feature = "rusttype")]
+pub(crate) const TEX_WIDTH: usize = 1024;
+#[cfg(feature = "rusttype")]
+pub(crate) const TEX_HEIGHT: usize = 1024;
+#[cfg(feature = "rusttype")]
+pub(crate) const MAX_TEX_WIDTH: usize = 4096;
+#[cfg(feature = "rusttype")]
+pub(crate) const MAX_TEX_HEIGHT: usize = 4096;
+const SUBPIXEL_STEPS: f32 = 4.0;
+const MAX_BLUR: usize = 20;
+const LAYOUT_CACHE_SIZE: usize = 4096;
+const MAX_INSTANCES: usize = 32;
+const SDF_SIZE: f32 = 64.0;
+const SDF_SPREAD: usize = 8;
+const APREC: i32 = 16;
+const ZPREC: i32 = 7;
+pub(crate) const ELLIPSIS: &str = "\u{2026}";
 
 pub type FontId = usize;
 
+// A variable font with the bits of its quantized axis values.
+type InstanceKey = (FontId, Vec<(Tag, u32)>);
+
+#[cfg(not(feature = "shaping"))]
+type Substitutions = HashMap<GlyphId, GlyphId>;
+
+// Instance ids stay assigned to their axis values, but only the most recently used instances
+// are kept loaded and evicted ones are loaded again on demand.
+#[derive(Default)]
+struct Instances {
+    ids: HashMap<InstanceKey, FontId>,
+    keys: HashMap<FontId, InstanceKey>,
+    loaded: HashMap<FontId, u64>,
+    clock: u64,
+}
+
 #[derive(Debug)]
 pub struct LayoutChar {
     id: FontId,
@@ -19,265 +65,3123 @@ pub struct LayoutChar {
     pub next_x: f32,
     pub c: char,
     pub idx: usize,
-    glyph: PositionedGlyph<'static>,
+    glyph: PlacedGlyph,
+    pub color: bool,
+    pub rotated: bool,
     pub uv: Bounds,
     pub bounds: Bounds,
 }
 
+#[derive(Debug, Copy, Clone)]
+struct PlacedGlyph {
+    id: GlyphId,
+    size: f32,
+    position: crate::Point,
+    bounds: Option<PixelBounds>,
+    hinted: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TextRow {
+    pub start: usize,
+    pub end: usize,
+    pub next: usize,
+    pub width: f32,
+    pub min_x: f32,
+    pub max_x: f32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TruncationMode {
+    End,
+    Start,
+    Middle,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TruncatedText {
+    pub head: Range<usize>,
+    pub tail: Range<usize>,
+}
+
+impl TruncatedText {
+    pub fn is_truncated(&self) -> bool {
+        self.head.end != self.tail.start
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextSpacing {
+    pub letter: f32,
+    pub word: f32,
+    pub tab_stops: Vec<f32>,
+    pub tab_width: f32,
+    pub pixel_advances: bool,
+    // OpenType features turned on or off, without shaping only `kern` and single substitutions
+    // are applied.
+    pub features: Vec<(Tag, bool)>,
+}
+
+impl TextSpacing {
+    // The distance from `x` to the next tab stop, the explicit stops are followed by stops
+    // every `tab_width`. Without any stops tabs are laid out like other characters.
+    fn tab_advance(&self, x: f32) -> Option<f32> {
+        if let Some(stop) = self.tab_stops.iter().find(|stop| **stop > x) {
+            return Some(stop - x);
+        }
+        if self.tab_width > 0.0 {
+            Some(((x / self.tab_width).floor() + 1.0) * self.tab_width - x)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct LayoutGlyph {
+    id: FontId,
+    glyph: GlyphId,
+    position: crate::Point,
+    idx: usize,
+    c: char,
+    x: f32,
+    next_x: f32,
+    rotated: bool,
+}
+
+// Shaped text that can be drawn again at any position and scale, positions are relative to
+// the origin of the layout.
+#[derive(Debug, Clone)]
+pub struct TextLayout {
+    pub(crate) font_id: FontId,
+    pub(crate) size: f32,
+    pub(crate) scale: f32,
+    pub(crate) vertical: bool,
+    pub(crate) extent: Extent,
+    glyphs: Vec<LayoutGlyph>,
+    // The start, baseline and width of each horizontal line.
+    pub(crate) lines: Vec<(f32, f32, f32)>,
+}
+
+impl TextLayout {
+    pub(crate) fn new(font_id: FontId, size: f32, vertical: bool) -> TextLayout {
+        TextLayout {
+            font_id,
+            size,
+            scale: 1.0,
+            vertical,
+            extent: Default::default(),
+            glyphs: Default::default(),
+            lines: Default::default(),
+        }
+    }
+
+    pub fn extent(&self) -> Extent {
+        Extent::new(
+            self.extent.width / self.scale,
+            self.extent.height / self.scale,
+        )
+    }
+}
+
+struct CachedLayout {
+    text: String,
+    align: Align,
+    spacing: TextSpacing,
+    layout: Arc<TextLayout>,
+    last_used: u64,
+}
+
+// The keys of the layouts are also ordered by their last use, so the least recently used
+// layout is found without a scan.
+#[derive(Default)]
+struct LayoutCache {
+    layouts: HashMap<u64, CachedLayout>,
+    by_use: BTreeMap<u64, u64>,
+    clock: u64,
+}
+
+impl LayoutCache {
+    fn clear(&mut self) {
+        self.layouts.clear();
+        self.by_use.clear();
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct GlyphPosition {
+    pub idx: usize,
+    pub x: f32,
+    pub next_x: f32,
+    pub min_x: f32,
+    pub max_x: f32,
+    pub rtl: bool,
+}
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+struct GlyphKey {
+    id: FontId,
+    glyph: GlyphId,
+    size: u32,
+    subpixel: (u8, u8),
+    blur: usize,
+    hinted: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct CachedGlyph {
+    uv: Bounds,
+    bounds: Bounds,
+}
+
+enum CacheOutcome {
+    Cached(CachedGlyph),
+    // The glyph could not be rasterized and is not drawn.
+    Empty,
+    Full,
+}
+
+struct ShapedGlyph {
+    cluster: usize,
+    rtl: bool,
+    glyph: Option<(FontId, GlyphId)>,
+    x_advance: f32,
+    x_offset: f32,
+    y_offset: f32,
+}
+
+struct CharPosition {
+    idx: usize,
+    rtl: bool,
+    c: char,
+    x: f32,
+    next_x: f32,
+    glyph: Option<(FontId, PlacedGlyph)>,
+}
+
+impl CharPosition {
+    fn bounds_x(&self) -> (f32, f32) {
+        match self.glyph.and_then(|(_, glyph)| glyph.bounds) {
+            Some(bb) => (bb.min_x as f32, bb.max_x as f32),
+            None => (self.x, self.next_x),
+        }
+    }
+}
+
+struct RowBuilder {
+    start: usize,
+    end: usize,
+    start_x: f32,
+    width: f32,
+    min_x: f32,
+    max_x: f32,
+    break_point: Option<(usize, f32, f32, usize, usize)>,
+}
+
+impl RowBuilder {
+    fn new(start: usize, x: f32, min_x: f32) -> RowBuilder {
+        RowBuilder {
+            start,
+            end: start,
+            start_x: x,
+            width: 0.0,
+            min_x: min_x - x,
+            max_x: 0.0,
+            break_point: None,
+        }
+    }
+
+    fn to_row(&self, end: usize, next: usize, width: f32, max_x: f32) -> TextRow {
+        TextRow {
+            start: self.start,
+            end,
+            next,
+            width,
+            min_x: self.min_x,
+            max_x,
+        }
+    }
+}
+
+// Characters set upright in vertical text, following the Vertical_Orientation property.
+fn is_upright(c: char) -> bool {
+    match c as u32 {
+        0x3008..=0x3011 | 0x3014..=0x301F | 0x3030 | 0x30A0 | 0x30FC => false,
+        0xFF08 | 0xFF09 | 0xFF0D | 0xFF1A..=0xFF1E | 0xFF3B | 0xFF3D | 0xFF3F => false,
+        0xFF5B..=0xFF60 | 0xFFE3 => false,
+        0x1100..=0x11FF
+        | 0x2E80..=0x2FFF
+        | 0x3000..=0x31FF
+        | 0x3200..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA960..=0xA97F
+        | 0xAC00..=0xD7FF
+        | 0xF900..=0xFAFF
+        | 0xFE10..=0xFE1F
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE7
+        | 0x1F000..=0x1FAFF
+        | 0x20000..=0x3FFFF => true,
+        _ => false,
+    }
+}
+
+pub(crate) fn is_newline(c: char) -> bool {
+    matches!(
+        c,
+        '\n' | '\r' | '\u{0b}' | '\u{0c}' | '\u{85}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct FontInfo {
+    pub family: String,
+    pub subfamily: String,
+    pub weight: u16,
+    pub italic: bool,
+}
+
+impl FontInfo {
+    pub(crate) fn read(data: &[u8], index: u32) -> anyhow::Result<FontInfo> {
+        let face = ttf_parser::Face::from_slice(data, index)?;
+        let name = |ids: &[u16]| {
+            ids.iter()
+                .filter_map(|id| {
+                    face.names()
+                        .into_iter()
+                        .filter(|name| name.name_id == *id && name.is_unicode())
+                        .find_map(|name| name.to_string())
+                })
+                .next()
+                .unwrap_or_default()
+        };
+
+        Ok(FontInfo {
+            family: name(&[
+                ttf_parser::name_id::TYPOGRAPHIC_FAMILY,
+                ttf_parser::name_id::FAMILY,
+            ]),
+            subfamily: name(&[
+                ttf_parser::name_id::TYPOGRAPHIC_SUBFAMILY,
+                ttf_parser::name_id::SUBFAMILY,
+            ]),
+            weight: face.weight().to_number(),
+            italic: face.style() != ttf_parser::Style::Normal,
+        })
+    }
+}
+
 struct FontData {
-    font: Font<'static>,
+    data: Arc<[u8]>,
+    index: u32,
+    font: Box<dyn FontFace>,
+    v_metrics: FaceMetrics,
+    info: FontInfo,
+    colr: Option<ColorLayers>,
+    has_bitmaps: bool,
+    bitmaps: RefCell<HashMap<GlyphId, bool>>,
+    metrics: FontMetrics,
+    vertical: VerticalMetrics,
     fallback_fonts: Vec<FontId>,
+    axes: Vec<(Tag, f32, f32)>,
+    #[cfg(feature = "shaping")]
+    variations: Vec<(Tag, f32)>,
+    // The substituted glyphs for each set of features, see `FontData::substitute`.
+    #[cfg(not(feature = "shaping"))]
+    substitutions: RefCell<HashMap<Vec<(Tag, bool)>, Substitutions>>,
 }
 
-pub struct Fonts {
-    fonts: Slab<FontData>,
-    fonts_by_name: HashMap<String, FontId>,
-    cache: Cache<'static>,
-    pub(crate) img: ImageId,
+// Extended metrics in font units, positions are above the baseline.
+struct FontMetrics {
+    x_height: f32,
+    cap_height: f32,
+    underline_position: f32,
+    underline_thickness: f32,
+    strikeout_position: f32,
+    strikeout_thickness: f32,
 }
 
-impl Fonts {
-    pub fn new<R: Renderer>(renderer: &mut R) -> anyhow::Result<Fonts> {
-        Ok(Fonts {
-            fonts: Default::default(),
-            fonts_by_name: Default::default(),
-            img: renderer.create_texture(
-                TextureType::Alpha,
-                TEX_WIDTH,
-                TEX_HEIGHT,
-                ImageFlags::empty(),
-                None,
-            )?,
-            cache: Cache::builder()
-                .multithread(true)
-                .dimensions(TEX_WIDTH as u32, TEX_HEIGHT as u32)
-                .build(),
-        })
+impl FontMetrics {
+    fn read(face: &ttf_parser::Face) -> FontMetrics {
+        let units_per_em = face.units_per_em() as f32;
+        let glyph_height = |c: char| {
+            face.glyph_index(c)
+                .and_then(|id| face.glyph_bounding_box(id))
+                .map(|bb| bb.y_max as f32)
+        };
+
+        let x_height = face
+            .x_height()
+            .map(|v| v as f32)
+            .or_else(|| glyph_height('x'))
+            .unwrap_or(face.ascender() as f32 * 0.5);
+        let cap_height = face
+            .capital_height()
+            .map(|v| v as f32)
+            .or_else(|| glyph_height('H'))
+            .unwrap_or(face.ascender() as f32 * 0.7);
+        let (underline_position, underline_thickness) = face
+            .underline_metrics()
+            .filter(|m| m.thickness > 0)
+            .map(|m| (m.position as f32, m.thickness as f32))
+            .unwrap_or((-units_per_em * 0.1, units_per_em / 14.0));
+        let (strikeout_position, strikeout_thickness) = face
+            .strikeout_metrics()
+            .filter(|m| m.thickness > 0)
+            .map(|m| (m.position as f32, m.thickness as f32))
+            .unwrap_or(((x_height + underline_thickness) * 0.5, underline_thickness));
+
+        FontMetrics {
+            x_height,
+            cap_height,
+            underline_position,
+            underline_thickness,
+            strikeout_position,
+            strikeout_thickness,
+        }
     }
+}
 
-    pub fn add_font<N: Into<String>, D: Into<Vec<u8>>>(
-        &mut self,
-        name: N,
-        data: D,
-    ) -> anyhow::Result<FontId> {
-        let font = Font::<'static>::from_bytes(data.into())?;
-        let fd = FontData {
-            font,
-            fallback_fonts: Default::default(),
+// Vertical metrics in font units from `vmtx` and `VORG`, fonts without them are laid out
+// vertically with their ascent and descent.
+struct VerticalMetrics {
+    // The advance and the top side bearing of each glyph.
+    glyphs: Vec<(f32, f32)>,
+    default_origin: Option<f32>,
+    origins: HashMap<GlyphId, f32>,
+}
+
+impl VerticalMetrics {
+    fn read(face: &ttf_parser::Face) -> VerticalMetrics {
+        let glyphs = match face.tables().vmtx {
+            Some(_) => (0..face.number_of_glyphs())
+                .map(|id| {
+                    let id = ttf_parser::GlyphId(id);
+                    (
+                        face.glyph_ver_advance(id).unwrap_or_default() as f32,
+                        face.glyph_ver_side_bearing(id).unwrap_or_default() as f32,
+                    )
+                })
+                .collect(),
+            None => Vec::new(),
         };
-        let id = self.fonts.insert(fd);
-        self.fonts_by_name.insert(name.into(), id);
-        Ok(id)
+        let vorg = face.tables().vorg;
+
+        VerticalMetrics {
+            glyphs,
+            default_origin: vorg.map(|vorg| vorg.default_y as f32),
+            origins: vorg
+                .map(|vorg| {
+                    vorg.metrics
+                        .into_iter()
+                        .map(|m| (m.glyph_id.0, m.y as f32))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
     }
+}
 
-    pub fn find<N: Borrow<str>>(&self, name: N) -> Option<FontId> {
-        self.fonts_by_name.get(name.borrow()).map(ToOwned::to_owned)
+impl FontData {
+    fn new(backend: &dyn FontBackend, data: Vec<u8>, index: u32) -> anyhow::Result<FontData> {
+        FontData::load(backend, data.into(), index, &[])
     }
 
-    pub fn add_fallback(&mut self, base: FontId, fallback: FontId) {
-        if let Some(fd) = self.fonts.get_mut(base) {
-            fd.fallback_fonts.push(fallback);
+    fn load(
+        backend: &dyn FontBackend,
+        data: Arc<[u8]>,
+        index: u32,
+        variations: &[(Tag, f32)],
+    ) -> anyhow::Result<FontData> {
+        let mut font = backend.load_face(data.clone(), index)?;
+        let mut face = ttf_parser::Face::from_slice(&data, index)?;
+        if !variations.is_empty() {
+            font.set_variations(variations);
+            for (tag, value) in variations {
+                face.set_variation(ttf_parser::Tag::from_bytes(tag), *value);
+            }
         }
+        let v_metrics = font.metrics();
+        let info = FontInfo::read(&data, index)?;
+        let colr = ColorLayers::parse(&face);
+        let metrics = FontMetrics::read(&face);
+        let vertical = VerticalMetrics::read(&face);
+        let axes = face
+            .variation_axes()
+            .into_iter()
+            .map(|axis| (axis.tag.to_bytes(), axis.min_value, axis.max_value))
+            .collect();
+        let has_bitmaps = [b"sbix", b"CBDT", b"EBDT"]
+            .iter()
+            .any(|tag| face.table_data(ttf_parser::Tag::from_bytes(tag)).is_some());
+        Ok(FontData {
+            data,
+            index,
+            font,
+            v_metrics,
+            info,
+            colr,
+            has_bitmaps,
+            bitmaps: Default::default(),
+            metrics,
+            vertical,
+            fallback_fonts: Default::default(),
+            axes,
+            #[cfg(feature = "shaping")]
+            variations: variations.to_vec(),
+            #[cfg(not(feature = "shaping"))]
+            substitutions: Default::default(),
+        })
     }
 
-    fn glyph(&self, id: FontId, c: char) -> Option<(FontId, Glyph<'static>)> {
-        if let Some(fd) = self.fonts.get(id) {
-            let glyph = fd.font.glyph(c);
-            if glyph.id().0 != 0 {
-                Some((id, glyph))
-            } else {
-                for id in &fd.fallback_fonts {
-                    if let Some(fd) = self.fonts.get(*id) {
-                        let glyph = fd.font.glyph(c);
-                        if glyph.id().0 != 0 {
-                            return Some((*id, glyph));
-                        }
+    // Applies the single substitutions of the enabled GSUB features, like `tnum` or `smcp`.
+    // Ligatures and contextual substitutions need shaping.
+    #[cfg(not(feature = "shaping"))]
+    fn substitute(&self, glyph: GlyphId, features: &[(Tag, bool)]) -> GlyphId {
+        if !features
+            .iter()
+            .any(|(tag, enabled)| *enabled && tag != b"kern")
+        {
+            return glyph;
+        }
+        if !self.substitutions.borrow().contains_key(features) {
+            let substitutions = self.read_substitutions(features);
+            self.substitutions
+                .borrow_mut()
+                .insert(features.to_vec(), substitutions);
+        }
+        self.substitutions.borrow()[features]
+            .get(&glyph)
+            .copied()
+            .unwrap_or(glyph)
+    }
+
+    // Resolves the single substitution lookups of the features for all glyphs at once.
+    #[cfg(not(feature = "shaping"))]
+    fn read_substitutions(&self, features: &[(Tag, bool)]) -> Substitutions {
+        use ttf_parser::gsub::{SingleSubstitution, SubstitutionSubtable};
+
+        let mut substitutions = HashMap::new();
+        let face = match ttf_parser::Face::from_slice(&self.data, self.index) {
+            Ok(face) => face,
+            Err(_) => return substitutions,
+        };
+        let gsub = match face.tables().gsub {
+            Some(gsub) => gsub,
+            None => return substitutions,
+        };
+
+        // Without a script to select, the features of the default script are used.
+        let script = gsub
+            .scripts
+            .find(ttf_parser::Tag::from_bytes(b"DFLT"))
+            .or_else(|| gsub.scripts.find(ttf_parser::Tag::from_bytes(b"latn")))
+            .or_else(|| gsub.scripts.get(0));
+        let language = match script.and_then(|script| script.default_language) {
+            Some(language) => language,
+            None => return substitutions,
+        };
+        let enabled = |tag: ttf_parser::Tag| {
+            matches!(
+                features
+                    .iter()
+                    .rev()
+                    .find(|(t, _)| ttf_parser::Tag::from_bytes(t) == tag),
+                Some((_, true))
+            )
+        };
+        let mut lookups = language
+            .feature_indices
+            .into_iter()
+            .filter_map(|n| gsub.features.get(n))
+            .filter(|feature| enabled(feature.tag))
+            .flat_map(|feature| feature.lookup_indices)
+            .collect::<Vec<_>>();
+        if lookups.is_empty() {
+            return substitutions;
+        }
+        lookups.sort_unstable();
+        lookups.dedup();
+        let lookups = lookups
+            .into_iter()
+            .filter_map(|n| gsub.lookups.get(n))
+            .collect::<Vec<_>>();
+
+        for id in 0..face.number_of_glyphs() {
+            let mut glyph = ttf_parser::GlyphId(id);
+            for lookup in &lookups {
+                for subtable in lookup.subtables.into_iter::<SubstitutionSubtable>() {
+                    let single = match subtable {
+                        SubstitutionSubtable::Single(single) => single,
+                        _ => continue,
+                    };
+                    if let Some(n) = single.coverage().get(glyph) {
+                        glyph = match single {
+                            SingleSubstitution::Format1 { delta, .. } => {
+                                ttf_parser::GlyphId((glyph.0 as i32 + delta as i32) as u16)
+                            }
+                            SingleSubstitution::Format2 { substitutes, .. } => {
+                                substitutes.get(n).unwrap_or(glyph)
+                            }
+                        };
+                        break;
                     }
                 }
-                None
             }
-        } else {
-            None
+            if glyph.0 != id {
+                substitutions.insert(id, glyph.0);
+            }
         }
+        substitutions
     }
 
-    fn render_texture<R: Renderer>(&mut self, renderer: &mut R) -> anyhow::Result<()> {
-        let img = self.img.clone();
-        self.cache.cache_queued(move |rect, data| {
-            renderer
-                .update_texture(
-                    img.clone(),
-                    rect.min.x as usize,
-                    rect.min.y as usize,
-                    (rect.max.x - rect.min.x) as usize,
-                    (rect.max.y - rect.min.y) as usize,
-                    data,
-                )
-                .unwrap();
-        })?;
-        Ok(())
+    // Sizes are the height from the descent to the ascent in pixels.
+    fn factor(&self, size: f32) -> f32 {
+        size / (self.v_metrics.ascent - self.v_metrics.descent)
     }
 
-    pub fn text_metrics(&self, id: FontId, size: f32) -> TextMetrics {
-        if let Some(fd) = self.fonts.get(id) {
-            let scale = Scale::uniform(size);
-            let v_metrics = fd.font.v_metrics(scale);
-            TextMetrics {
-                ascender: v_metrics.descent,
-                descender: v_metrics.descent,
-                line_gap: v_metrics.line_gap,
-            }
+    fn scaled_v_metrics(&self, size: f32) -> FaceMetrics {
+        let factor = self.factor(size);
+        FaceMetrics {
+            ascent: self.v_metrics.ascent * factor,
+            descent: self.v_metrics.descent * factor,
+            line_gap: self.v_metrics.line_gap * factor,
+        }
+    }
+
+    fn glyph_scale(&self, size: f32) -> GlyphScale {
+        let factor = self.factor(size);
+        GlyphScale {
+            x: factor,
+            y: factor,
+        }
+    }
+
+    // Grid fits the outlines vertically, the x-height is scaled to whole pixels so that with a
+    // snapped baseline lowercase letters start and end on pixel edges.
+    fn hinted_scale(&self, size: f32) -> GlyphScale {
+        let scale = self.glyph_scale(size);
+        let x_height = self.metrics.x_height * scale.y;
+        if x_height < 1.0 {
+            return scale;
+        }
+        GlyphScale {
+            x: scale.x,
+            y: scale.y * x_height.round() / x_height,
+        }
+    }
+
+    fn placed_scale(&self, glyph: &PlacedGlyph) -> GlyphScale {
+        if glyph.hinted {
+            self.hinted_scale(glyph.size)
         } else {
-            TextMetrics {
-                ascender: 0.0,
-                descender: 0.0,
-                line_gap: 0.0,
-            }
+            self.glyph_scale(glyph.size)
         }
     }
 
-    pub fn text_size(&self, text: &str, id: FontId, size: f32, spacing: f32) -> Extent {
-        if let Some(fd) = self.fonts.get(id) {
-            let scale = Scale::uniform(size);
-            let v_metrics = fd.font.v_metrics(scale);
-            let mut extent = Extent::new(
-                0.0,
-                v_metrics.ascent - v_metrics.descent + v_metrics.line_gap,
-            );
-            let mut last_glyph = None;
-            let mut char_count = 0;
+    fn place(&self, glyph: GlyphId, size: f32, position: crate::Point) -> PlacedGlyph {
+        self.place_hinted(glyph, size, position, false)
+    }
 
-            for c in text.chars() {
-                if let Some((_, glyph)) = self.glyph(id, c) {
-                    let glyph = glyph.scaled(scale);
-                    let h_metrics = glyph.h_metrics();
-                    extent.width += h_metrics.advance_width;
+    fn place_hinted(
+        &self,
+        glyph: GlyphId,
+        size: f32,
+        position: crate::Point,
+        hinted: bool,
+    ) -> PlacedGlyph {
+        let mut placed = PlacedGlyph {
+            id: glyph,
+            size,
+            position,
+            bounds: None,
+            hinted,
+        };
+        placed.bounds = self
+            .font
+            .glyph_bounds(glyph, self.placed_scale(&placed), position);
+        placed
+    }
 
-                    if let Some(last_glyph) = last_glyph {
-                        extent.width += fd.font.pair_kerning(scale, last_glyph, glyph.id());
-                    }
+    // Bitmap glyphs are looked up when they are first drawn.
+    fn is_bitmap_glyph(&self, glyph: GlyphId) -> bool {
+        if !self.has_bitmaps {
+            return false;
+        }
+        *self.bitmaps.borrow_mut().entry(glyph).or_insert_with(|| {
+            ttf_parser::Face::from_slice(&self.data, self.index)
+                .map(|face| {
+                    face.glyph_raster_image(ttf_parser::GlyphId(glyph), u16::MAX)
+                        .is_some()
+                })
+                .unwrap_or_default()
+        })
+    }
 
-                    last_glyph = Some(glyph.id());
-                    char_count += 1;
-                }
-            }
+    fn is_color_glyph(&self, glyph: GlyphId) -> bool {
+        self.is_bitmap_glyph(glyph)
+            || self
+                .colr
+                .as_ref()
+                .map(|colr| colr.contains(glyph))
+                .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum AtlasKind {
+    Coverage,
+    DistanceField,
+    Lcd,
+    Color,
+}
+
+struct GlyphAtlas {
+    kind: AtlasKind,
+    atlas: Atlas,
+    resized: bool,
+    // Glyphs without pixels are cached as `None`, so they are not rasterized again.
+    glyphs: HashMap<GlyphKey, Option<CachedGlyph>>,
+    img: ImageId,
+}
 
-            if char_count >= 2 {
-                extent.width += spacing * (char_count - 1) as f32;
+impl GlyphAtlas {
+    fn new<R: Renderer>(
+        renderer: &mut R,
+        size: (usize, usize),
+        kind: AtlasKind,
+    ) -> anyhow::Result<GlyphAtlas> {
+        let mut atlas = match kind {
+            AtlasKind::Coverage => {
+                Atlas::new(size.0, size.1, TextureType::Alpha, ImageFlags::empty())
+            }
+            AtlasKind::DistanceField => {
+                Atlas::new(size.0, size.1, TextureType::Alpha, ImageFlags::SDF)
             }
+            AtlasKind::Lcd => Atlas::new(size.0, size.1, TextureType::RGB, ImageFlags::LCD),
+            AtlasKind::Color => {
+                Atlas::new(size.0, size.1, TextureType::RGBA, ImageFlags::PREMULTIPLIED)
+            }
+        };
+        Ok(GlyphAtlas {
+            kind,
+            img: atlas.create_texture(renderer)?,
+            atlas,
+            resized: false,
+            glyphs: Default::default(),
+        })
+    }
 
-            extent
+    fn grow(&mut self, max_size: (usize, usize)) -> bool {
+        let (width, height) = (self.atlas.width, self.atlas.height);
+        let (max_width, max_height) = max_size;
+
+        if width >= max_width && height >= max_height {
+            return false;
+        }
+
+        if height < max_height && (height <= width || width >= max_width) {
+            self.atlas.expand(width, (height * 2).min(max_height));
         } else {
-            Default::default()
+            self.atlas.expand((width * 2).min(max_width), height);
         }
+        self.resized = true;
+        true
+    }
+
+    fn reset(&mut self) {
+        self.atlas.reset();
+        self.glyphs.clear();
+        self.resized = true;
     }
 
-    pub fn layout_text<R: Renderer>(
+    fn flush<R: Renderer>(
         &mut self,
         renderer: &mut R,
-        text: &str,
-        id: FontId,
-        position: crate::Point,
-        size: f32,
-        align: Align,
-        spacing: f32,
-        cache: bool,
-        result: &mut Vec<LayoutChar>,
+        retired_images: &mut Vec<ImageId>,
     ) -> anyhow::Result<()> {
-        result.clear();
-
-        if let Some(fd) = self.fonts.get(id) {
-            let mut offset = Point { x: 0.0, y: 0.0 };
-            let scale = Scale::uniform(size);
-            let v_metrics = fd.font.v_metrics(scale);
-
-            let sz = if align.contains(Align::CENTER)
-                || align.contains(Align::RIGHT)
-                || align.contains(Align::MIDDLE)
-            {
-                self.text_size(text, id, size, spacing)
-            } else {
-                Extent::new(0.0, 0.0)
-            };
+        if self.resized {
+            self.resized = false;
+            retired_images.push(self.img);
+            self.img = self.atlas.create_texture(renderer)?;
+        } else {
+            self.atlas.flush(renderer, self.img)?;
+        }
+        Ok(())
+    }
 
-            if align.contains(Align::CENTER) {
-                offset.x -= sz.width / 2.0;
-            } else if align.contains(Align::RIGHT) {
-                offset.x -= sz.width;
-            }
+    fn cache_glyph<F>(&mut self, key: GlyphKey, rasterize: F) -> CacheOutcome
+    where
+        F: FnOnce() -> Option<(Vec<u8>, usize, usize, Bounds)>,
+    {
+        match self.glyphs.get(&key) {
+            Some(Some(cached)) => return CacheOutcome::Cached(*cached),
+            Some(None) => return CacheOutcome::Empty,
+            None => {}
+        }
 
-            if align.contains(Align::MIDDLE) {
-                offset.y = v_metrics.descent + sz.height / 2.0;
-            } else if align.contains(Align::BOTTOM) {
-                offset.y = v_metrics.descent;
-            } else if align.contains(Align::TOP) {
-                offset.y = v_metrics.ascent;
+        let (data, width, height, bounds) = match rasterize() {
+            Some(raster) => raster,
+            None => {
+                self.glyphs.insert(key, None);
+                return CacheOutcome::Empty;
             }
+        };
+        let (x, y) = match self.atlas.add_rect(width, height) {
+            Some(pos) => pos,
+            None => return CacheOutcome::Full,
+        };
+        self.atlas.write(x, y, width, height, &data);
 
-            let mut position = Point {
-                x: position.x + offset.x,
-                y: position.y + offset.y,
-            };
-            let mut last_glyph = None;
+        let cached = CachedGlyph {
+            uv: Bounds {
+                min: crate::Point::new(x as f32, y as f32),
+                max: crate::Point::new((x + width) as f32, (y + height) as f32),
+            },
+            bounds,
+        };
+        self.glyphs.insert(key, Some(cached));
+        CacheOutcome::Cached(cached)
+    }
 
-            for (idx, c) in text.chars().enumerate() {
-                if let Some((id, glyph)) = self.glyph(id, c) {
-                    let g = glyph.scaled(scale);
-                    let h_metrics = g.h_metrics();
+    fn cache_glyphs(
+        &mut self,
+        fonts: &BTreeMap<FontId, FontData>,
+        blur: usize,
+        result: &mut [LayoutChar],
+    ) -> bool {
+        let color = self.kind == AtlasKind::Color;
 
-                    let glyph = g.positioned(Point {
-                        x: position.x,
-                        y: position.y,
-                    });
+        for lc in result.iter_mut().filter(|lc| lc.color == color) {
+            let font = &fonts[&lc.id];
+            let position = lc.glyph.position;
+            let size = lc.glyph.size;
 
-                    let mut next_x = position.x + h_metrics.advance_width;
-                    if let Some(last_glyph) = last_glyph {
-                        next_x += fd.font.pair_kerning(scale, last_glyph, glyph.id());
+            if self.kind == AtlasKind::DistanceField {
+                // Distance fields are rasterized once at a fixed size and scaled into place.
+                let key = GlyphKey {
+                    id: lc.id,
+                    glyph: lc.glyph.id,
+                    size: 0,
+                    subpixel: (0, 0),
+                    blur: 0,
+                    hinted: false,
+                };
+                let cached = match self.cache_glyph(key, || rasterize_sdf(font, &lc.glyph)) {
+                    CacheOutcome::Cached(cached) => cached,
+                    CacheOutcome::Empty => {
+                        skip_glyph(lc);
+                        continue;
                     }
+                    CacheOutcome::Full => return false,
+                };
+                let scale = size / SDF_SIZE;
+                lc.uv = cached.uv;
+                lc.bounds = Bounds {
+                    min: crate::Point::new(
+                        position.x + cached.bounds.min.x * scale,
+                        position.y + cached.bounds.min.y * scale,
+                    ),
+                    max: crate::Point::new(
+                        position.x + cached.bounds.max.x * scale,
+                        position.y + cached.bounds.max.y * scale,
+                    ),
+                };
+                continue;
+            }
 
-                    if let Some(bb) = glyph.pixel_bounding_box() {
-                        self.cache.queue_glyph(id, glyph.clone());
+            let (origin_x, origin_y, key) = if color {
+                // Color glyphs are neither blurred nor positioned at subpixel offsets.
+                let key = GlyphKey {
+                    id: lc.id,
+                    glyph: lc.glyph.id,
+                    size: (size * 10.0).round() as u32,
+                    subpixel: (0, 0),
+                    blur: 0,
+                    hinted: lc.glyph.hinted,
+                };
+                (position.x.round(), position.y.round(), key)
+            } else {
+                let x = (position.x * SUBPIXEL_STEPS).round();
+                let y = (position.y * SUBPIXEL_STEPS).round();
+                let origin_x = (x / SUBPIXEL_STEPS).floor();
+                let origin_y = (y / SUBPIXEL_STEPS).floor();
+                let key = GlyphKey {
+                    id: lc.id,
+                    glyph: lc.glyph.id,
+                    size: (size * 10.0).round() as u32,
+                    subpixel: (
+                        (x - origin_x * SUBPIXEL_STEPS) as u8,
+                        (y - origin_y * SUBPIXEL_STEPS) as u8,
+                    ),
+                    blur,
+                    hinted: lc.glyph.hinted,
+                };
+                (origin_x, origin_y, key)
+            };
 
-                        result.push(LayoutChar {
-                            id,
-                            idx,
-                            c,
-                            x: position.x,
-                            next_x,
-                            glyph: glyph.clone(),
-                            uv: Default::default(),
-                            bounds: Bounds {
-                                min: (bb.min.x, bb.min.y).into(),
-                                max: (bb.max.x, bb.max.y).into(),
-                            },
-                        });
-                    }
+            let glyph = &lc.glyph;
+            let cached = if color {
+                self.cache_glyph(key, || rasterize_color(font, glyph, key))
+            } else if self.kind == AtlasKind::Lcd {
+                self.cache_glyph(key, || rasterize_lcd(font, glyph, key))
+            } else {
+                self.cache_glyph(key, || rasterize(font, glyph, key))
+            };
 
-                    position.x = next_x;
-                    last_glyph = Some(glyph.id());
+            match cached {
+                CacheOutcome::Cached(cached) => {
+                    lc.uv = cached.uv;
+                    lc.bounds = Bounds {
+                        min: crate::Point::new(
+                            origin_x + cached.bounds.min.x,
+                            origin_y + cached.bounds.min.y,
+                        ),
+                        max: crate::Point::new(
+                            origin_x + cached.bounds.max.x,
+                            origin_y + cached.bounds.max.y,
+                        ),
+                    };
                 }
+                CacheOutcome::Empty => skip_glyph(lc),
+                CacheOutcome::Full => return false,
             }
+        }
+        true
+    }
 
-            if cache {
-                self.render_texture(renderer)?;
-
-                for lc in result {
-                    if let Ok(Some((uv, _))) = self.cache.rect_for(lc.id, &lc.glyph) {
-                        lc.uv = Bounds {
-                            min: crate::Point {
-                                x: uv.min.x,
-                                y: uv.min.y,
-                            },
-                            max: crate::Point {
-                                x: uv.max.x,
-                                y: uv.max.y,
-                            },
-                        };
-                    }
+    fn cache<R: Renderer>(
+        &mut self,
+        renderer: &mut R,
+        fonts: &BTreeMap<FontId, FontData>,
+        blur: usize,
+        max_size: (usize, usize),
+        retired_images: &mut Vec<ImageId>,
+        result: &mut [LayoutChar],
+    ) -> anyhow::Result<ImageId> {
+        while !self.cache_glyphs(fonts, blur, result) {
+            if !self.grow(max_size) {
+                // The atlas can not grow anymore, continue on a new page with only the
+                // glyphs of this text, the previous page stays alive until the next frame.
+                self.reset();
+                if !self.cache_glyphs(fonts, blur, result) {
+                    anyhow::bail!(
+                        "the glyphs do not fit into the font atlas ({}x{})",
+                        self.atlas.width,
+                        self.atlas.height
+                    );
                 }
+                break;
             }
         }
 
-        Ok(())
+        self.flush(renderer, retired_images)?;
+
+        let color = self.kind == AtlasKind::Color;
+        for lc in result.iter_mut().filter(|lc| lc.color == color) {
+            lc.uv.min.x /= self.atlas.width as f32;
+            lc.uv.min.y /= self.atlas.height as f32;
+            lc.uv.max.x /= self.atlas.width as f32;
+            lc.uv.max.y /= self.atlas.height as f32;
+        }
+
+        Ok(self.img)
+    }
+}
+
+pub struct Fonts {
+    backend: Box<dyn FontBackend>,
+    fonts: RefCell<BTreeMap<FontId, FontData>>,
+    next_font_id: Cell<FontId>,
+    fonts_by_name: HashMap<String, FontId>,
+    atlas: GlyphAtlas,
+    sdf_atlas: Option<GlyphAtlas>,
+    lcd_atlas: Option<GlyphAtlas>,
+    color_atlas: Option<GlyphAtlas>,
+    atlas_size: (usize, usize),
+    max_atlas_size: (usize, usize),
+    retired_images: Vec<ImageId>,
+    layout_cache: LayoutCache,
+    instances: RefCell<Instances>,
+    #[cfg(feature = "system-fonts")]
+    system_fonts: Vec<SystemFont>,
+    #[cfg(feature = "system-fonts")]
+    loaded_system_fonts: RefCell<HashMap<usize, FontId>>,
+    #[cfg(feature = "system-fonts")]
+    missing_chars: RefCell<HashSet<char>>,
+}
+
+impl Fonts {
+    pub fn new<R: Renderer>(
+        renderer: &mut R,
+        atlas_size: (usize, usize),
+        max_atlas_size: (usize, usize),
+        backend: Box<dyn FontBackend>,
+    ) -> anyhow::Result<Fonts> {
+        if atlas_size.0 == 0 || atlas_size.1 == 0 {
+            anyhow::bail!("invalid font atlas size: {}x{}", atlas_size.0, atlas_size.1);
+        }
+
+        Ok(Fonts {
+            backend,
+            fonts: Default::default(),
+            next_font_id: Default::default(),
+            fonts_by_name: Default::default(),
+            atlas: GlyphAtlas::new(renderer, atlas_size, AtlasKind::Coverage)?,
+            sdf_atlas: None,
+            lcd_atlas: None,
+            color_atlas: None,
+            atlas_size,
+            max_atlas_size: (
+                max_atlas_size.0.max(atlas_size.0),
+                max_atlas_size.1.max(atlas_size.1),
+            ),
+            retired_images: Default::default(),
+            layout_cache: Default::default(),
+            instances: Default::default(),
+            #[cfg(feature = "system-fonts")]
+            system_fonts: Default::default(),
+            #[cfg(feature = "system-fonts")]
+            loaded_system_fonts: Default::default(),
+            #[cfg(feature = "system-fonts")]
+            missing_chars: Default::default(),
+        })
+    }
+
+    pub fn add_font<N: Into<String>, D: Into<Vec<u8>>>(
+        &mut self,
+        name: N,
+        data: D,
+        index: u32,
+    ) -> anyhow::Result<FontId> {
+        let fd = FontData::new(&*self.backend, data.into(), index)?;
+        let id = self.insert_font(fd);
+        self.fonts_by_name.insert(name.into(), id);
+        Ok(id)
+    }
+
+    pub fn find<N: Borrow<str>>(&self, name: N) -> Option<FontId> {
+        self.fonts_by_name.get(name.borrow()).map(ToOwned::to_owned)
+    }
+
+    pub fn find_by(&self, family: &str, weight: u16, italic: bool) -> Option<FontId> {
+        let found = self
+            .fonts
+            .borrow()
+            .iter()
+            .filter(|(_, fd)| fd.info.family.eq_ignore_ascii_case(family))
+            .min_by_key(|(_, fd)| style_distance(&fd.info, weight, italic))
+            .map(|(id, _)| *id);
+        found.or_else(|| self.find_system_font_by(family, weight, italic))
+    }
+
+    #[cfg(feature = "system-fonts")]
+    pub fn load_system_fonts(&mut self) {
+        for dir in fontdb::standard_dirs() {
+            fontdb::scan_dir(&dir, &mut self.system_fonts);
+        }
+        self.missing_chars.get_mut().clear();
+        self.layout_cache.clear();
+    }
+
+    #[cfg(feature = "system-fonts")]
+    pub fn load_fonts_dir(&mut self, dir: &std::path::Path) {
+        fontdb::scan_dir(dir, &mut self.system_fonts);
+        self.missing_chars.get_mut().clear();
+        self.layout_cache.clear();
+    }
+
+    #[cfg(feature = "system-fonts")]
+    fn load_system_font(&self, n: usize) -> Option<FontId> {
+        if let Some(id) = self.loaded_system_fonts.borrow().get(&n) {
+            return Some(*id);
+        }
+
+        let sf = &self.system_fonts[n];
+        let fd = FontData::new(&*self.backend, std::fs::read(&sf.path).ok()?, sf.index).ok()?;
+        let id = self.insert_font(fd);
+        self.loaded_system_fonts.borrow_mut().insert(n, id);
+        Some(id)
+    }
+
+    #[cfg(feature = "system-fonts")]
+    fn find_system_font_by(&self, family: &str, weight: u16, italic: bool) -> Option<FontId> {
+        let (n, _) = self
+            .system_fonts
+            .iter()
+            .enumerate()
+            .filter(|(_, sf)| sf.info.family.eq_ignore_ascii_case(family))
+            .min_by_key(|(_, sf)| style_distance(&sf.info, weight, italic))?;
+        self.load_system_font(n)
+    }
+
+    #[cfg(not(feature = "system-fonts"))]
+    fn find_system_font_by(&self, _family: &str, _weight: u16, _italic: bool) -> Option<FontId> {
+        None
+    }
+
+    #[cfg(feature = "system-fonts")]
+    fn system_fallback(&self, id: FontId, text: &str, idx: usize) -> Option<FontId> {
+        use unicode_script::{Script, UnicodeScript};
+
+        let c = text[idx..].chars().next()?;
+        if self.missing_chars.borrow().contains(&c) {
+            return None;
+        }
+
+        // Prefer the font covering most of the script run, so words do not mix fonts.
+        let script = c.script();
+        let run: Vec<char> = text[idx..]
+            .chars()
+            .take_while(|c| {
+                let s = c.script();
+                s == script || s == Script::Common || s == Script::Inherited
+            })
+            .collect();
+        let (weight, italic) = self
+            .font(id)
+            .map(|fd| (fd.info.weight, fd.info.italic))
+            .unwrap_or((400, false));
+        let best = self
+            .system_fonts
+            .iter()
+            .enumerate()
+            .filter(|(_, sf)| sf.covers(c))
+            .max_by_key(|(n, sf)| {
+                (
+                    run.iter().filter(|c| sf.covers(**c)).count(),
+                    std::cmp::Reverse(style_distance(&sf.info, weight, italic)),
+                    std::cmp::Reverse(*n),
+                )
+            })
+            .map(|(n, _)| n);
+
+        let fallback = match best.and_then(|n| self.load_system_font(n)) {
+            Some(fallback) => fallback,
+            None => {
+                self.missing_chars.borrow_mut().insert(c);
+                return None;
+            }
+        };
+        if let Some(fd) = self.fonts.borrow_mut().get_mut(&id) {
+            if !fd.fallback_fonts.contains(&fallback) {
+                fd.fallback_fonts.push(fallback);
+            }
+        }
+        Some(fallback)
+    }
+
+    #[cfg(not(feature = "system-fonts"))]
+    fn system_fallback(&self, _id: FontId, _text: &str, _idx: usize) -> Option<FontId> {
+        None
+    }
+
+    // Loads the system fonts the text falls back to before laying it out, so no font is added
+    // while the glyph loops keep the fonts borrowed.
+    #[cfg(feature = "system-fonts")]
+    fn load_fallbacks(&self, id: FontId, text: &str) {
+        for (idx, c) in text.char_indices() {
+            if self.glyph(id, c).is_none() {
+                self.system_fallback(id, text, idx);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "system-fonts"))]
+    fn load_fallbacks(&self, _id: FontId, _text: &str) {}
+
+    pub fn info(&self, id: FontId) -> Option<FontInfo> {
+        self.font(id).map(|fd| fd.info.clone())
+    }
+
+    fn font(&self, id: FontId) -> Option<Ref<'_, FontData>> {
+        Ref::filter_map(self.fonts.borrow(), |fonts| fonts.get(&id)).ok()
+    }
+
+    fn insert_font(&self, fd: FontData) -> FontId {
+        let id = self.next_font_id.get();
+        self.next_font_id.set(id + 1);
+        self.fonts.borrow_mut().insert(id, fd);
+        id
+    }
+
+    // The instance of a variable font with the given axis values, created on first use. Axes
+    // the font does not have are ignored, without any of its axes this is the font itself.
+    pub fn instance(&self, id: FontId, variations: &[(Tag, f32)]) -> FontId {
+        if variations.is_empty() {
+            return id;
+        }
+
+        let values = match self.font(id) {
+            Some(fd) => fd
+                .axes
+                .iter()
+                .filter_map(|(tag, min, max)| {
+                    let (_, value) = variations.iter().rev().find(|(t, _)| t == tag)?;
+                    Some((*tag, quantize_axis(*value, *min, *max).to_bits()))
+                })
+                .collect::<Vec<_>>(),
+            None => return id,
+        };
+        if values.is_empty() {
+            return id;
+        }
+
+        let key = (id, values);
+        let instance = self.instances.borrow().ids.get(&key).copied();
+        let instance = instance.unwrap_or_else(|| {
+            let instance = self.next_font_id.get();
+            self.next_font_id.set(instance + 1);
+            let mut instances = self.instances.borrow_mut();
+            instances.ids.insert(key.clone(), instance);
+            instances.keys.insert(instance, key);
+            instance
+        });
+        if self.load_instance(instance) {
+            instance
+        } else {
+            id
+        }
+    }
+
+    // Marks the instance as used, loading it again if it was evicted. At most `MAX_INSTANCES`
+    // instances stay loaded.
+    fn load_instance(&self, id: FontId) -> bool {
+        let mut instances = self.instances.borrow_mut();
+        instances.clock += 1;
+        let clock = instances.clock;
+        if let Some(used) = instances.loaded.get_mut(&id) {
+            *used = clock;
+            return true;
+        }
+        let (base, values) = match instances.keys.get(&id) {
+            Some(key) => key.clone(),
+            None => return self.font(id).is_some(),
+        };
+
+        let (data, index, fallback_fonts) = match self.font(base) {
+            Some(fd) => (fd.data.clone(), fd.index, fd.fallback_fonts.clone()),
+            None => return false,
+        };
+        let variations = values
+            .iter()
+            .map(|(tag, value)| (*tag, f32::from_bits(*value)))
+            .collect::<Vec<_>>();
+        let mut fd = match FontData::load(&*self.backend, data, index, &variations) {
+            Ok(fd) => fd,
+            Err(_) => return false,
+        };
+        fd.fallback_fonts = fallback_fonts;
+
+        let mut fonts = self.fonts.borrow_mut();
+        if instances.loaded.len() >= MAX_INSTANCES {
+            let lru = instances
+                .loaded
+                .iter()
+                .min_by_key(|(_, used)| **used)
+                .map(|(id, _)| *id);
+            if let Some(lru) = lru {
+                instances.loaded.remove(&lru);
+                fonts.remove(&lru);
+            }
+        }
+        fonts.insert(id, fd);
+        instances.loaded.insert(id, clock);
+        true
+    }
+
+    // Loads the instances the layout was made with again if they were evicted since.
+    fn load_layout_fonts(&self, layout: &TextLayout) {
+        let mut ids = vec![layout.font_id];
+        for lg in &layout.glyphs {
+            if !ids.contains(&lg.id) {
+                ids.push(lg.id);
+            }
+        }
+        for id in ids {
+            self.load_instance(id);
+        }
+    }
+
+    pub fn add_fallback(&mut self, base: FontId, fallback: FontId) {
+        if let Some(fd) = self.fonts.get_mut().get_mut(&base) {
+            fd.fallback_fonts.push(fallback);
+        }
+        // Cached layouts may miss glyphs the new fallback provides.
+        self.layout_cache.clear();
+    }
+
+    fn glyph(&self, id: FontId, c: char) -> Option<(FontId, GlyphId)> {
+        if let Some(fd) = self.font(id) {
+            if let Some(glyph) = fd.font.glyph_index(c) {
+                Some((id, glyph))
+            } else {
+                for id in &fd.fallback_fonts {
+                    if let Some(fd) = self.font(*id) {
+                        if let Some(glyph) = fd.font.glyph_index(c) {
+                            return Some((*id, glyph));
+                        }
+                    }
+                }
+                None
+            }
+        } else {
+            None
+        }
+    }
+
+    fn find_glyph(&self, id: FontId, text: &str, idx: usize, c: char) -> Option<(FontId, GlyphId)> {
+        self.glyph(id, c).or_else(|| {
+            self.system_fallback(id, text, idx)?;
+            self.glyph(id, c)
+        })
+    }
+
+    pub(crate) fn ink_extent(&self, lc: &LayoutChar, min_y: f32, max_y: f32) -> Option<(f32, f32)> {
+        if lc.color {
+            return if lc.bounds.max.y > min_y && lc.bounds.min.y < max_y {
+                Some((lc.bounds.min.x, lc.bounds.max.x))
+            } else {
+                None
+            };
+        }
+
+        let bb = lc.glyph.bounds?;
+        if bb.max_y as f32 <= min_y || bb.min_y as f32 >= max_y {
+            return None;
+        }
+
+        let fd = self.font(lc.id)?;
+        let width = bb.width();
+        let mut coverage = vec![0; width * bb.height()];
+        fd.font.rasterize(
+            lc.glyph.id,
+            fd.placed_scale(&lc.glyph),
+            lc.glyph.position,
+            &mut coverage,
+            width,
+        );
+
+        let mut extent: Option<(i32, i32)> = None;
+        for (n, v) in coverage.iter().enumerate() {
+            let y = bb.min_y + (n / width) as i32;
+            if *v as f32 > 0.05 * 255.0 && (y + 1) as f32 > min_y && (y as f32) < max_y {
+                let x = bb.min_x + (n % width) as i32;
+                extent = Some(match extent {
+                    Some((min_x, max_x)) => (min_x.min(x), max_x.max(x + 1)),
+                    None => (x, x + 1),
+                });
+            }
+        }
+        extent.map(|(min_x, max_x)| (min_x as f32, max_x as f32))
+    }
+
+    pub fn delete_retired_images<R: Renderer>(&mut self, renderer: &mut R) -> anyhow::Result<()> {
+        for img in self.retired_images.drain(..) {
+            renderer.delete_texture(img)?;
+        }
+        Ok(())
+    }
+
+    pub fn text_metrics(&self, id: FontId, size: f32) -> TextMetrics {
+        if let Some(fd) = self.font(id) {
+            let v_metrics = fd.scaled_v_metrics(size);
+            let factor = fd.factor(size);
+            TextMetrics {
+                ascender: v_metrics.ascent,
+                descender: v_metrics.descent,
+                line_gap: v_metrics.line_gap,
+                x_height: fd.metrics.x_height * factor,
+                cap_height: fd.metrics.cap_height * factor,
+                underline_position: fd.metrics.underline_position * factor,
+                underline_thickness: fd.metrics.underline_thickness * factor,
+                strikeout_position: fd.metrics.strikeout_position * factor,
+                strikeout_thickness: fd.metrics.strikeout_thickness * factor,
+            }
+        } else {
+            TextMetrics {
+                ascender: 0.0,
+                descender: 0.0,
+                line_gap: 0.0,
+                x_height: 0.0,
+                cap_height: 0.0,
+                underline_position: 0.0,
+                underline_thickness: 0.0,
+                strikeout_position: 0.0,
+                strikeout_thickness: 0.0,
+            }
+        }
+    }
+
+    pub fn text_size(&self, text: &str, id: FontId, size: f32, spacing: &TextSpacing) -> Extent {
+        // The font must not stay borrowed while the glyphs load fallback fonts.
+        let v_metrics = self.font(id).map(|fd| fd.scaled_v_metrics(size));
+        if let Some(v_metrics) = v_metrics {
+            let mut extent = Extent::new(
+                0.0,
+                v_metrics.ascent - v_metrics.descent + v_metrics.line_gap,
+            );
+            self.for_each_glyph(text, id, Default::default(), size, spacing, |cp| {
+                extent.width = cp.next_x
+            });
+            extent
+        } else {
+            Default::default()
+        }
+    }
+
+    pub fn truncate_text(
+        &self,
+        text: &str,
+        id: FontId,
+        size: f32,
+        spacing: &TextSpacing,
+        max_width: f32,
+        mode: TruncationMode,
+    ) -> TruncatedText {
+        truncate(
+            text,
+            |range| self.text_size(&text[range], id, size, spacing).width,
+            self.text_size(ELLIPSIS, id, size, spacing).width,
+            max_width,
+            mode,
+        )
+    }
+
+    fn shape(
+        &self,
+        text: &str,
+        id: FontId,
+        size: f32,
+        features: &[(Tag, bool)],
+        result: &mut Vec<ShapedGlyph>,
+    ) {
+        let bidi = BidiInfo::new(text, None);
+        let start = result.len();
+
+        for para in &bidi.paragraphs {
+            let (levels, runs) = bidi.visual_runs(para, para.range.clone());
+            for run in runs {
+                let rtl = levels[run.start].is_rtl();
+                self.shape_text(text, run, rtl, id, features, result);
+            }
+        }
+
+        // Glyphs are shaped in font units of their own font.
+        let fonts = self.fonts.borrow();
+        for sg in &mut result[start..] {
+            if let Some((id, _)) = sg.glyph {
+                let factor = fonts[&id].factor(size);
+                sg.x_advance *= factor;
+                sg.x_offset *= factor;
+                sg.y_offset *= factor;
+            }
+        }
+    }
+
+    #[cfg(not(feature = "shaping"))]
+    fn shape_text(
+        &self,
+        text: &str,
+        range: std::ops::Range<usize>,
+        rtl: bool,
+        id: FontId,
+        features: &[(Tag, bool)],
+        result: &mut Vec<ShapedGlyph>,
+    ) {
+        let start = result.len();
+        let kerning = !features.contains(&(*b"kern", false));
+        let mut last_glyph: Option<(FontId, GlyphId, usize)> = None;
+
+        for (idx, c) in text[range.clone()].char_indices() {
+            let idx = range.start + idx;
+            if let Some((id, glyph_id)) = self.find_glyph(id, text, idx, c) {
+                let fonts = self.fonts.borrow();
+                let fd = &fonts[&id];
+                let glyph_id = fd.substitute(glyph_id, features);
+                let mut x_advance = fd.font.advance(glyph_id);
+
+                if let Some((last_id, last_glyph, n)) = last_glyph.filter(|_| kerning) {
+                    if last_id == id {
+                        let kerning = fd.font.kerning(last_glyph, glyph_id);
+                        // The glyphs are reversed below, so the kerning goes to the glyph on the left.
+                        if rtl {
+                            x_advance += kerning;
+                        } else {
+                            result[n].x_advance += kerning;
+                        }
+                    }
+                }
+
+                last_glyph = Some((id, glyph_id, result.len()));
+                result.push(ShapedGlyph {
+                    cluster: idx,
+                    rtl,
+                    glyph: Some((id, glyph_id)),
+                    x_advance,
+                    x_offset: 0.0,
+                    y_offset: 0.0,
+                });
+            } else {
+                result.push(ShapedGlyph {
+                    cluster: idx,
+                    rtl,
+                    glyph: None,
+                    x_advance: 0.0,
+                    x_offset: 0.0,
+                    y_offset: 0.0,
+                });
+            }
+        }
+
+        if rtl {
+            result[start..].reverse();
+        }
+    }
+
+    #[cfg(feature = "shaping")]
+    fn shape_text(
+        &self,
+        text: &str,
+        range: std::ops::Range<usize>,
+        rtl: bool,
+        id: FontId,
+        features: &[(Tag, bool)],
+        result: &mut Vec<ShapedGlyph>,
+    ) {
+        let covers = |id: FontId, c: char| self.fonts.borrow()[&id].font.glyph_index(c).is_some();
+        let mut runs = Vec::new();
+        let mut run: Option<(FontId, usize)> = None;
+
+        for (idx, c) in text[range.clone()].char_indices() {
+            let idx = range.start + idx;
+            match run {
+                Some((run_id, _)) if covers(run_id, c) => {}
+                _ => {
+                    let font_id = self.find_glyph(id, text, idx, c).map(|(id, _)| id);
+                    match (run, font_id) {
+                        (Some((run_id, start)), Some(font_id)) => {
+                            runs.push((run_id, start..idx));
+                            run = Some((font_id, idx));
+                        }
+                        (None, _) => run = Some((font_id.unwrap_or(id), idx)),
+                        (Some(_), None) => {}
+                    }
+                }
+            }
+        }
+
+        if let Some((run_id, start)) = run {
+            runs.push((run_id, start..range.end));
+        }
+
+        // Each run is shaped in visual order, right-to-left runs are also placed from the end.
+        if rtl {
+            runs.reverse();
+        }
+
+        // Each font is parsed once for all of its runs.
+        let mut data = HashMap::new();
+        for (run_id, _) in &runs {
+            if let Some(fd) = self.font(*run_id) {
+                data.entry(*run_id)
+                    .or_insert_with(|| (fd.data.clone(), fd.index, fd.variations.clone()));
+            }
+        }
+        let faces: HashMap<_, _> = data
+            .iter()
+            .filter_map(|(id, (data, index, variations))| {
+                let mut face = rustybuzz::Face::from_slice(data, *index)?;
+                let variations: Vec<_> = variations
+                    .iter()
+                    .map(|(tag, value)| rustybuzz::Variation {
+                        tag: rustybuzz::Tag::from_bytes(tag),
+                        value: *value,
+                    })
+                    .collect();
+                face.set_variations(&variations);
+                Some((*id, face))
+            })
+            .collect();
+        let features: Vec<_> = features
+            .iter()
+            .map(|(tag, enabled)| {
+                rustybuzz::Feature::new(rustybuzz::Tag::from_bytes(tag), *enabled as u32, ..)
+            })
+            .collect();
+
+        for (run_id, range) in runs {
+            if let Some(face) = faces.get(&run_id) {
+                shape_run(face, &features, text, range, rtl, run_id, result);
+            }
+        }
+    }
+
+    fn for_each_glyph<F: FnMut(CharPosition)>(
+        &self,
+        text: &str,
+        id: FontId,
+        position: crate::Point,
+        size: f32,
+        spacing: &TextSpacing,
+        mut f: F,
+    ) {
+        let mut glyphs = Vec::new();
+        let mut x = position.x;
+        let mut last_cluster = None;
+
+        self.load_fallbacks(id, text);
+        self.shape(text, id, size, &spacing.features, &mut glyphs);
+
+        for sg in glyphs {
+            let c = text[sg.cluster..].chars().next().unwrap_or_default();
+
+            if c == '\t' {
+                if let Some(advance) = spacing.tab_advance(x - position.x) {
+                    // The tab ends exactly on the stop, no letter spacing follows it.
+                    last_cluster = None;
+                    f(CharPosition {
+                        idx: sg.cluster,
+                        rtl: sg.rtl,
+                        c,
+                        x,
+                        next_x: x + advance,
+                        glyph: None,
+                    });
+                    x += advance;
+                    continue;
+                }
+            }
+
+            if let Some((id, glyph_id)) = sg.glyph {
+                if matches!(last_cluster, Some(cluster) if cluster != sg.cluster) {
+                    x += spacing.letter;
+                }
+                last_cluster = Some(sg.cluster);
+
+                let mut advance = if c == ' ' {
+                    sg.x_advance + spacing.word
+                } else {
+                    sg.x_advance
+                };
+                if spacing.pixel_advances {
+                    advance = advance.round();
+                }
+                let glyph = self.fonts.borrow()[&id].place(
+                    glyph_id,
+                    size,
+                    crate::Point::new(x + sg.x_offset, position.y - sg.y_offset),
+                );
+                f(CharPosition {
+                    idx: sg.cluster,
+                    rtl: sg.rtl,
+                    c,
+                    x,
+                    next_x: x + advance,
+                    glyph: Some((id, glyph)),
+                });
+                x += advance;
+            } else {
+                f(CharPosition {
+                    idx: sg.cluster,
+                    rtl: sg.rtl,
+                    c,
+                    x,
+                    next_x: x,
+                    glyph: None,
+                });
+            }
+        }
+    }
+
+    fn measure_chars(
+        &self,
+        text: &str,
+        id: FontId,
+        size: f32,
+        spacing: &TextSpacing,
+        mut x: f32,
+        chars: &mut Vec<(usize, char, f32, f32, f32, f32)>,
+    ) -> f32 {
+        let mut glyphs = Vec::new();
+        let glyph_spacing = TextSpacing {
+            letter: 0.0,
+            ..spacing.clone()
+        };
+        self.for_each_glyph(text, id, Default::default(), size, &glyph_spacing, |cp| {
+            let (min_x, max_x) = cp.bounds_x();
+            glyphs.push((
+                cp.idx,
+                cp.c,
+                cp.glyph.is_some(),
+                cp.next_x - cp.x,
+                min_x - cp.x,
+                max_x - cp.x,
+            ));
+        });
+
+        // Shaped right-to-left runs come out in visual order, rows are measured in logical order.
+        glyphs.sort_by_key(|g| g.0);
+
+        let mut last_cluster = None;
+        for (idx, c, found, advance, min_x, max_x) in glyphs {
+            if found {
+                if matches!(last_cluster, Some(cluster) if cluster != idx) {
+                    x += spacing.letter;
+                }
+                last_cluster = Some(idx);
+            } else if c == '\t' {
+                last_cluster = None;
+            }
+            chars.push((idx, c, x, x + advance, x + min_x, x + max_x));
+            x += advance;
+        }
+        x
+    }
+
+    // Lays out a column with the center at `position.x` starting at `position.y`, sideways
+    // glyphs are positioned in a frame rotated by 90 degrees, see `LayoutChar::rotated`.
+    fn for_each_glyph_vertical<F: FnMut(CharPosition, bool)>(
+        &self,
+        text: &str,
+        id: FontId,
+        position: crate::Point,
+        size: f32,
+        spacing: &TextSpacing,
+        mut f: F,
+    ) {
+        self.load_fallbacks(id, text);
+        let v_metrics = match self.font(id) {
+            Some(fd) => fd.scaled_v_metrics(size),
+            None => return,
+        };
+        let baseline = (v_metrics.ascent + v_metrics.descent) / 2.0 - position.x;
+        let mut y = position.y;
+        let mut last_cluster = None;
+        let mut glyphs = Vec::new();
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((start, c)) = chars.next() {
+            if is_upright(c) {
+                match self.find_glyph(id, text, start, c) {
+                    Some((id, glyph_id)) => {
+                        if last_cluster.is_some() {
+                            y += spacing.letter;
+                        }
+                        last_cluster = Some(start);
+
+                        let (advance, origin) = self.vertical_metrics(id, glyph_id, size);
+                        let glyph = {
+                            let fd = &self.fonts.borrow()[&id];
+                            let x = position.x - fd.font.advance(glyph_id) * fd.factor(size) / 2.0;
+                            fd.place(glyph_id, size, crate::Point::new(x, y + origin))
+                        };
+                        f(
+                            CharPosition {
+                                idx: start,
+                                rtl: false,
+                                c,
+                                x: y,
+                                next_x: y + advance,
+                                glyph: Some((id, glyph)),
+                            },
+                            false,
+                        );
+                        y += advance;
+                    }
+                    None => f(
+                        CharPosition {
+                            idx: start,
+                            rtl: false,
+                            c,
+                            x: y,
+                            next_x: y,
+                            glyph: None,
+                        },
+                        false,
+                    ),
+                }
+                continue;
+            }
+
+            // Runs of sideways characters are shaped horizontally and rotated as a whole.
+            let mut end = start + c.len_utf8();
+            while let Some((idx, c)) = chars.peek().copied() {
+                if is_upright(c) {
+                    break;
+                }
+                end = idx + c.len_utf8();
+                chars.next();
+            }
+
+            glyphs.clear();
+            self.shape(&text[start..end], id, size, &spacing.features, &mut glyphs);
+            for sg in &glyphs {
+                let idx = start + sg.cluster;
+                let c = text[idx..].chars().next().unwrap_or_default();
+
+                if let Some((id, glyph_id)) = sg.glyph {
+                    if matches!(last_cluster, Some(cluster) if cluster != idx) {
+                        y += spacing.letter;
+                    }
+                    last_cluster = Some(idx);
+
+                    let glyph = self.fonts.borrow()[&id].place(
+                        glyph_id,
+                        size,
+                        crate::Point::new(y + sg.x_offset, baseline - sg.y_offset),
+                    );
+                    f(
+                        CharPosition {
+                            idx,
+                            rtl: sg.rtl,
+                            c,
+                            x: y,
+                            next_x: y + sg.x_advance,
+                            glyph: Some((id, glyph)),
+                        },
+                        true,
+                    );
+                    y += sg.x_advance;
+                } else {
+                    f(
+                        CharPosition {
+                            idx,
+                            rtl: sg.rtl,
+                            c,
+                            x: y,
+                            next_x: y,
+                            glyph: None,
+                        },
+                        true,
+                    );
+                }
+            }
+        }
+    }
+
+    // The vertical advance and the distance from the top of the glyph cell to the baseline.
+    fn vertical_metrics(&self, id: FontId, glyph: GlyphId, size: f32) -> (f32, f32) {
+        let fonts = self.fonts.borrow();
+        let fd = &fonts[&id];
+        let units = fd.v_metrics;
+        let factor = fd.factor(size);
+        let vertical = &fd.vertical;
+        let metrics = vertical.glyphs.get(glyph as usize);
+
+        let advance = metrics
+            .map(|(advance, _)| *advance)
+            .unwrap_or(units.ascent - units.descent);
+        // Without an origin in `VORG` the glyph top is the top side bearing above its bounds.
+        let origin = vertical
+            .origins
+            .get(&glyph)
+            .copied()
+            .or(vertical.default_origin)
+            .or_else(|| {
+                let (_, tsb) = metrics?;
+                let bb = fd.font.glyph_bounds(
+                    glyph,
+                    GlyphScale { x: 1.0, y: 1.0 },
+                    Default::default(),
+                )?;
+                Some(tsb - bb.min_y as f32)
+            })
+            .unwrap_or(units.ascent);
+        (advance * factor, origin * factor)
+    }
+
+    pub fn break_lines(
+        &self,
+        text: &str,
+        id: FontId,
+        size: f32,
+        spacing: &TextSpacing,
+        break_width: f32,
+        result: &mut Vec<TextRow>,
+    ) {
+        result.clear();
+
+        let mut chars = Vec::new();
+        self.measure_chars(text, id, size, spacing, 0.0, &mut chars);
+        wrap_lines(text, &chars, break_width, result);
+    }
+
+    pub fn break_rich_text(
+        &self,
+        text: &RichText,
+        scale: f32,
+        variations: &[(Tag, f32)],
+        spacing: impl Fn(&TextStyle) -> TextSpacing,
+        break_width: f32,
+        result: &mut Vec<RichTextRow>,
+    ) {
+        result.clear();
+
+        // Spans are measured one after another and broken as a single string, so a word may
+        // continue across a change of style.
+        let mut full_text = String::new();
+        let mut chars = Vec::new();
+        let mut x = 0.0;
+        for span in &text.spans {
+            let n = chars.len();
+            x = self.measure_chars(
+                &span.text,
+                self.instance(span.style.font_id, variations),
+                span.style.font_size * scale,
+                &spacing(&span.style),
+                x,
+                &mut chars,
+            );
+            for ch in &mut chars[n..] {
+                ch.0 += full_text.len();
+            }
+            full_text.push_str(&span.text);
+        }
+
+        let mut rows = Vec::new();
+        wrap_lines(&full_text, &chars, break_width, &mut rows);
+
+        for row in rows {
+            let start = text.offset(row.start);
+            let end = if row.end > row.start {
+                let last = text.offset(row.end - 1);
+                TextOffset {
+                    span: last.span,
+                    idx: last.idx + 1,
+                }
+            } else {
+                start
+            };
+
+            let mut spans = text
+                .segments(start, end)
+                .map(|(n, _, _)| n)
+                .collect::<Vec<_>>();
+            if spans.is_empty() {
+                spans.push(start.span);
+            }
+
+            let metrics = spans
+                .into_iter()
+                .map(|n| {
+                    let style = &text.spans[n].style;
+                    let id = self.instance(style.font_id, variations);
+                    self.text_metrics(id, style.font_size * scale)
+                })
+                .reduce(|metrics, m| TextMetrics {
+                    ascender: metrics.ascender.max(m.ascender),
+                    descender: metrics.descender.min(m.descender),
+                    line_gap: metrics.line_gap.max(m.line_gap),
+                    ..metrics
+                })
+                .unwrap();
+
+            result.push(RichTextRow {
+                start,
+                end,
+                next: text.offset(row.next),
+                width: row.width,
+                min_x: row.min_x,
+                max_x: row.max_x,
+                ascender: metrics.ascender,
+                descender: metrics.descender,
+                height: metrics.line_height(),
+            });
+        }
+    }
+
+    fn vertical_offset(&self, id: FontId, size: f32, align: Align) -> Option<f32> {
+        let v_metrics = self.font(id)?.scaled_v_metrics(size);
+
+        Some(if align.contains(Align::MIDDLE) {
+            v_metrics.descent + (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap) / 2.0
+        } else if align.contains(Align::BOTTOM) {
+            v_metrics.descent
+        } else if align.contains(Align::TOP) {
+            v_metrics.ascent
+        } else {
+            0.0
+        })
+    }
+
+    pub fn line_bounds(&self, id: FontId, size: f32, y: f32, align: Align) -> (f32, f32) {
+        match (self.font(id), self.vertical_offset(id, size, align)) {
+            (Some(fd), Some(offset)) => {
+                let v_metrics = fd.scaled_v_metrics(size);
+                (
+                    y + offset - v_metrics.ascent,
+                    y + offset - v_metrics.descent,
+                )
+            }
+            _ => (y, y),
+        }
+    }
+
+    pub fn text_bounds(
+        &self,
+        text: &str,
+        id: FontId,
+        position: crate::Point,
+        size: f32,
+        align: Align,
+        spacing: &TextSpacing,
+    ) -> (f32, Bounds) {
+        let offset = match self.align_offset(text, id, size, align, spacing) {
+            Some(offset) => offset,
+            None => {
+                return (
+                    0.0,
+                    Bounds {
+                        min: position,
+                        max: position,
+                    },
+                )
+            }
+        };
+        let position = crate::Point {
+            x: position.x + offset.x,
+            y: position.y + offset.y,
+        };
+        let mut advance = 0.0;
+        let mut bounds: Option<Bounds> = None;
+
+        self.for_each_glyph(text, id, position, size, spacing, |cp| {
+            advance = cp.next_x - position.x;
+            if let Some(bb) = cp.glyph.and_then(|(_, glyph)| glyph.bounds) {
+                let b = bounds.get_or_insert(Bounds {
+                    min: (bb.min_x, bb.min_y).into(),
+                    max: (bb.max_x, bb.max_y).into(),
+                });
+                b.min.x = b.min.x.min(bb.min_x as f32);
+                b.min.y = b.min.y.min(bb.min_y as f32);
+                b.max.x = b.max.x.max(bb.max_x as f32);
+                b.max.y = b.max.y.max(bb.max_y as f32);
+            }
+        });
+
+        (
+            advance,
+            bounds.unwrap_or(Bounds {
+                min: position,
+                max: position,
+            }),
+        )
+    }
+
+    pub fn align_offset(
+        &self,
+        text: &str,
+        id: FontId,
+        size: f32,
+        align: Align,
+        spacing: &TextSpacing,
+    ) -> Option<crate::Point> {
+        let mut offset = crate::Point::new(0.0, self.vertical_offset(id, size, align)?);
+
+        if align.contains(Align::CENTER) {
+            offset.x -= self.text_size(text, id, size, spacing).width / 2.0;
+        } else if align.contains(Align::RIGHT) {
+            offset.x -= self.text_size(text, id, size, spacing).width;
+        }
+
+        Some(offset)
+    }
+
+    pub fn text_outlines(
+        &self,
+        text: &str,
+        id: FontId,
+        position: crate::Point,
+        size: f32,
+        align: Align,
+        spacing: &TextSpacing,
+    ) -> Vec<Vec<Contour>> {
+        let mut result = Vec::new();
+
+        if let Some(offset) = self.align_offset(text, id, size, align, spacing) {
+            let position = crate::Point {
+                x: position.x + offset.x,
+                y: position.y + offset.y,
+            };
+
+            self.for_each_glyph(text, id, position, size, spacing, |cp| {
+                if let Some((id, glyph)) = cp.glyph {
+                    let fd = &self.fonts.borrow()[&id];
+                    result.push(fd.font.outline(
+                        glyph.id,
+                        fd.glyph_scale(glyph.size),
+                        glyph.position,
+                    ));
+                }
+            });
+        }
+
+        result
+    }
+
+    pub fn glyph_positions(
+        &self,
+        text: &str,
+        id: FontId,
+        position: crate::Point,
+        size: f32,
+        align: Align,
+        spacing: &TextSpacing,
+    ) -> Vec<GlyphPosition> {
+        let mut result = Vec::new();
+
+        if let Some(offset) = self.align_offset(text, id, size, align, spacing) {
+            let position = crate::Point {
+                x: position.x + offset.x,
+                y: position.y + offset.y,
+            };
+
+            self.for_each_glyph(text, id, position, size, spacing, |cp| {
+                let (min_x, max_x) = cp.bounds_x();
+                result.push(GlyphPosition {
+                    idx: cp.idx,
+                    x: cp.x,
+                    next_x: cp.next_x,
+                    min_x: min_x.min(cp.x),
+                    max_x: max_x.max(cp.next_x),
+                    rtl: cp.rtl,
+                });
+            });
+        }
+
+        result
+    }
+
+    pub fn layout_line(
+        &self,
+        layout: &mut TextLayout,
+        text: &str,
+        position: crate::Point,
+        align: Align,
+        spacing: &TextSpacing,
+    ) {
+        let (id, size) = (layout.font_id, layout.size);
+        let glyphs = &mut layout.glyphs;
+        let mut push = |cp: CharPosition, rotated: bool| {
+            if let Some((id, glyph)) = cp.glyph {
+                glyphs.push(LayoutGlyph {
+                    id,
+                    glyph: glyph.id,
+                    position: glyph.position,
+                    idx: cp.idx,
+                    c: cp.c,
+                    x: cp.x,
+                    next_x: cp.next_x,
+                    rotated,
+                });
+            }
+        };
+
+        if layout.vertical {
+            let v_metrics = match self.font(id) {
+                Some(fd) => fd.scaled_v_metrics(size),
+                None => return,
+            };
+            let width = v_metrics.ascent - v_metrics.descent;
+            let mut length = 0.0;
+            self.for_each_glyph_vertical(text, id, Default::default(), size, spacing, |cp, _| {
+                length = cp.next_x
+            });
+
+            // Horizontal alignment places the column, vertical alignment the text within it.
+            let x = if align.contains(Align::CENTER) {
+                position.x
+            } else if align.contains(Align::RIGHT) {
+                position.x - width / 2.0
+            } else {
+                position.x + width / 2.0
+            };
+            let y = if align.contains(Align::MIDDLE) {
+                position.y - length / 2.0
+            } else if align.contains(Align::BOTTOM) {
+                position.y - length
+            } else {
+                position.y
+            };
+            self.for_each_glyph_vertical(text, id, (x, y).into(), size, spacing, push);
+            layout.extent.width += width;
+            layout.extent.height = layout.extent.height.max(length);
+        } else {
+            let offset = match self.align_offset(text, id, size, align, spacing) {
+                Some(offset) => offset,
+                None => return,
+            };
+            let position = crate::Point {
+                x: position.x + offset.x,
+                y: position.y + offset.y,
+            };
+            let mut width = 0.0;
+            self.for_each_glyph(text, id, position, size, spacing, |cp| {
+                width = cp.next_x - position.x;
+                push(cp, false);
+            });
+            layout.lines.push((position.x, position.y, width));
+            layout.extent.width = layout.extent.width.max(width);
+            layout.extent.height += self.text_metrics(id, size).line_height();
+        }
+    }
+
+    pub fn cached_layout(
+        &mut self,
+        text: &str,
+        id: FontId,
+        size: f32,
+        align: Align,
+        spacing: &TextSpacing,
+        vertical: bool,
+    ) -> Arc<TextLayout> {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        id.hash(&mut hasher);
+        size.to_bits().hash(&mut hasher);
+        align.hash(&mut hasher);
+        spacing.letter.to_bits().hash(&mut hasher);
+        spacing.word.to_bits().hash(&mut hasher);
+        for stop in &spacing.tab_stops {
+            stop.to_bits().hash(&mut hasher);
+        }
+        spacing.tab_width.to_bits().hash(&mut hasher);
+        spacing.pixel_advances.hash(&mut hasher);
+        spacing.features.hash(&mut hasher);
+        vertical.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let cache = &mut self.layout_cache;
+        cache.clock += 1;
+        if let Some(cached) = cache.layouts.get_mut(&key) {
+            if cached.text == text
+                && cached.layout.font_id == id
+                && cached.layout.size == size
+                && cached.layout.vertical == vertical
+                && cached.align == align
+                && cached.spacing == *spacing
+            {
+                cache.by_use.remove(&cached.last_used);
+                cache.by_use.insert(cache.clock, key);
+                cached.last_used = cache.clock;
+                return cached.layout.clone();
+            }
+        }
+
+        let mut layout = TextLayout::new(id, size, vertical);
+        self.layout_line(&mut layout, text, Default::default(), align, spacing);
+        let layout = Arc::new(layout);
+
+        let cache = &mut self.layout_cache;
+        let replaced = cache.layouts.insert(
+            key,
+            CachedLayout {
+                text: text.to_string(),
+                align,
+                spacing: spacing.clone(),
+                layout: layout.clone(),
+                last_used: cache.clock,
+            },
+        );
+        if let Some(replaced) = replaced {
+            cache.by_use.remove(&replaced.last_used);
+        }
+        cache.by_use.insert(cache.clock, key);
+        if cache.layouts.len() > LAYOUT_CACHE_SIZE {
+            if let Some((&last_used, &key)) = cache.by_use.iter().next() {
+                cache.by_use.remove(&last_used);
+                cache.layouts.remove(&key);
+            }
+        }
+        layout
+    }
+
+    // Places the glyphs of the layout with its origin at `position`, the layout is scaled by
+    // `scale`.
+    pub fn place_layout(
+        &self,
+        layout: &TextLayout,
+        position: crate::Point,
+        scale: f32,
+        snap: bool,
+        hinted: bool,
+        result: &mut Vec<LayoutChar>,
+    ) {
+        result.clear();
+
+        self.load_layout_fonts(layout);
+        let fonts = self.fonts.borrow();
+        let size = layout.size * scale;
+        for lg in &layout.glyphs {
+            // Fonts of the layout that could not be loaded again are skipped.
+            let fd = match fonts.get(&lg.id) {
+                Some(fd) => fd,
+                None => continue,
+            };
+            // Sideways glyphs are positioned in a frame rotated by 90 degrees.
+            let origin = if lg.rotated {
+                crate::Point::new(position.y, -position.x)
+            } else {
+                position
+            };
+            let mut pos = crate::Point::new(
+                origin.x + lg.position.x * scale,
+                origin.y + lg.position.y * scale,
+            );
+            if snap {
+                pos = (pos.x.round(), pos.y.round()).into();
+            }
+
+            let glyph = fd.place_hinted(lg.glyph, size, pos, hinted && !lg.rotated);
+            let color = fd.is_color_glyph(glyph.id);
+            let bounds = match glyph.bounds {
+                Some(bb) => Bounds {
+                    min: (bb.min_x, bb.min_y).into(),
+                    max: (bb.max_x, bb.max_y).into(),
+                },
+                // Bitmap glyphs do not need an outline.
+                None if color => Default::default(),
+                None => continue,
+            };
+            result.push(LayoutChar {
+                id: lg.id,
+                idx: lg.idx,
+                c: lg.c,
+                x: origin.x + lg.x * scale,
+                next_x: origin.x + lg.next_x * scale,
+                glyph,
+                color,
+                rotated: lg.rotated,
+                uv: Default::default(),
+                bounds,
+            });
+        }
+    }
+
+    pub fn rasterize_glyphs<R: Renderer>(
+        &mut self,
+        renderer: &mut R,
+        blur: f32,
+        kind: AtlasKind,
+        result: &mut [LayoutChar],
+    ) -> anyhow::Result<(Option<ImageId>, Option<ImageId>)> {
+        let fonts = self.fonts.borrow();
+        let blur = (blur.round().max(0.0) as usize).min(MAX_BLUR);
+
+        let img = if result.iter().any(|lc| !lc.color) {
+            let atlas = match kind {
+                AtlasKind::DistanceField | AtlasKind::Lcd => {
+                    let atlas = if kind == AtlasKind::Lcd {
+                        &mut self.lcd_atlas
+                    } else {
+                        &mut self.sdf_atlas
+                    };
+                    if atlas.is_none() {
+                        *atlas = Some(GlyphAtlas::new(renderer, self.atlas_size, kind)?);
+                    }
+                    atlas.as_mut().unwrap()
+                }
+                _ => &mut self.atlas,
+            };
+            Some(atlas.cache(
+                renderer,
+                &fonts,
+                blur,
+                self.max_atlas_size,
+                &mut self.retired_images,
+                result,
+            )?)
+        } else {
+            None
+        };
+
+        let color_img = if result.iter().any(|lc| lc.color) {
+            if self.color_atlas.is_none() {
+                self.color_atlas = Some(GlyphAtlas::new(
+                    renderer,
+                    self.atlas_size,
+                    AtlasKind::Color,
+                )?);
+            }
+            Some(self.color_atlas.as_mut().unwrap().cache(
+                renderer,
+                &fonts,
+                0,
+                self.max_atlas_size,
+                &mut self.retired_images,
+                result,
+            )?)
+        } else {
+            None
+        };
+
+        Ok((img, color_img))
+    }
+}
+
+fn wrap_lines(
+    text: &str,
+    chars: &[(usize, char, f32, f32, f32, f32)],
+    break_width: f32,
+    result: &mut Vec<TextRow>,
+) {
+    let mut row: Option<RowBuilder> = None;
+    let mut n = 0;
+
+    for (pos, opportunity) in unicode_linebreak::linebreaks(text) {
+        let seg_start = n;
+        while n < chars.len() && chars[n].0 < pos {
+            let (idx, c, x, next_x, min_x, max_x) = chars[n];
+            n += 1;
+
+            if c.is_whitespace() {
+                continue;
+            }
+
+            let r = match &mut row {
+                Some(r) => r,
+                None => {
+                    row = Some(RowBuilder::new(idx, x, min_x));
+                    row.as_mut().unwrap()
+                }
+            };
+
+            if next_x - r.start_x > break_width && r.end > r.start {
+                match r.break_point.take() {
+                    Some((end, width, max_x, next, break_n)) => {
+                        // Break after the last word, the new row starts at the word break.
+                        result.push(r.to_row(end, next, width, max_x));
+                        let (idx, _, x, _, min_x, _) = chars[break_n..]
+                            .iter()
+                            .copied()
+                            .find(|(_, c, ..)| !c.is_whitespace())
+                            .unwrap();
+                        *r = RowBuilder::new(idx, x, min_x);
+                    }
+                    None => {
+                        // The current word is longer than the row, break it right here.
+                        result.push(r.to_row(r.end, idx, r.width, r.max_x));
+                        *r = RowBuilder::new(idx, x, min_x);
+                    }
+                }
+            }
+
+            r.end = idx + c.len_utf8();
+            r.width = next_x - r.start_x;
+            r.max_x = max_x - r.start_x;
+        }
+
+        match opportunity {
+            BreakOpportunity::Mandatory => match row.take() {
+                Some(r) => result.push(r.to_row(r.end, pos, r.width, r.max_x)),
+                None if n > seg_start && is_newline(chars[n - 1].1) => {
+                    let start = chars[seg_start].0;
+                    result.push(TextRow {
+                        start,
+                        end: start,
+                        next: pos,
+                        width: 0.0,
+                        min_x: 0.0,
+                        max_x: 0.0,
+                    });
+                }
+                None => {}
+            },
+            BreakOpportunity::Allowed => {
+                if let Some(r) = &mut row {
+                    r.break_point = Some((r.end, r.width, r.max_x, pos, n));
+                }
+            }
+        }
+    }
+}
+
+// Axis values are clamped and snapped to 1/256 of the axis range, so animated values share
+// instances.
+fn quantize_axis(value: f32, min: f32, max: f32) -> f32 {
+    if max <= min {
+        return min;
+    }
+    let step = (max - min) / 256.0;
+    min + ((value.clamp(min, max) - min) / step).round() * step
+}
+
+// Truncates the text to `max_width` as measured by `width`, leaving room for an ellipsis that
+// is `ellipsis_width` wide.
+fn truncate(
+    text: &str,
+    width: impl Fn(Range<usize>) -> f32,
+    ellipsis_width: f32,
+    max_width: f32,
+    mode: TruncationMode,
+) -> TruncatedText {
+    if width(0..text.len()) <= max_width {
+        return TruncatedText {
+            head: 0..text.len(),
+            tail: text.len()..text.len(),
+        };
+    }
+
+    let boundaries = text
+        .char_indices()
+        .map(|(idx, _)| idx)
+        .chain(Some(text.len()))
+        .collect::<Vec<_>>();
+    let available = max_width - ellipsis_width;
+
+    // The longest head that fits into the given width.
+    let fit_head = |max_width: f32| {
+        let n = boundaries.partition_point(|end| width(0..*end) <= max_width);
+        let end = boundaries[n.max(1) - 1];
+        text[..end].trim_end().len()
+    };
+
+    // The longest tail that fits into the given width, not overlapping the head.
+    let fit_tail = |head: usize, max_width: f32| {
+        let n = boundaries.partition_point(|start| width(*start..text.len()) > max_width);
+        let start = boundaries.get(n).copied().unwrap_or(text.len()).max(head);
+        text.len() - text[start..].trim_start().len()
+    };
+
+    match mode {
+        TruncationMode::End => TruncatedText {
+            head: 0..fit_head(available),
+            tail: text.len()..text.len(),
+        },
+        TruncationMode::Start => TruncatedText {
+            head: 0..0,
+            tail: fit_tail(0, available)..text.len(),
+        },
+        TruncationMode::Middle => {
+            let head = fit_head(available * 0.5);
+            let tail = fit_tail(head, available - width(0..head));
+            TruncatedText {
+                head: 0..head,
+                tail: tail..text.len(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "shaping")]
+fn shape_run(
+    face: &rustybuzz::Face,
+    features: &[rustybuzz::Feature],
+    text: &str,
+    range: std::ops::Range<usize>,
+    rtl: bool,
+    id: FontId,
+    result: &mut Vec<ShapedGlyph>,
+) {
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(&text[range.clone()]);
+    buffer.set_direction(if rtl {
+        rustybuzz::Direction::RightToLeft
+    } else {
+        rustybuzz::Direction::LeftToRight
+    });
+    buffer.guess_segment_properties();
+    let output = rustybuzz::shape(face, features, buffer);
+
+    for (info, pos) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+        let found = info.glyph_id != 0;
+        result.push(ShapedGlyph {
+            cluster: range.start + info.cluster as usize,
+            rtl,
+            glyph: if found {
+                Some((id, info.glyph_id as GlyphId))
+            } else {
+                None
+            },
+            x_advance: if found { pos.x_advance as f32 } else { 0.0 },
+            x_offset: pos.x_offset as f32,
+            y_offset: pos.y_offset as f32,
+        });
+    }
+}
+
+fn style_distance(info: &FontInfo, weight: u16, italic: bool) -> (bool, (u8, u16)) {
+    (info.italic != italic, weight_distance(weight, info.weight))
+}
+
+// Follows the CSS font matching order: lighter weights first below 400, heavier weights first
+// above 500 and up to 500 before going lighter in between.
+fn weight_distance(desired: u16, weight: u16) -> (u8, u16) {
+    if weight == desired {
+        (0, 0)
+    } else if (400..=500).contains(&desired) {
+        if weight > desired && weight <= 500 {
+            (1, weight - desired)
+        } else if weight < desired {
+            (2, desired - weight)
+        } else {
+            (3, weight - desired)
+        }
+    } else if desired < 400 {
+        if weight < desired {
+            (1, desired - weight)
+        } else {
+            (2, weight - desired)
+        }
+    } else if weight > desired {
+        (1, weight - desired)
+    } else {
+        (2, desired - weight)
+    }
+}
+
+fn rasterize(
+    font: &FontData,
+    glyph: &PlacedGlyph,
+    key: GlyphKey,
+) -> Option<(Vec<u8>, usize, usize, Bounds)> {
+    let scale = font.placed_scale(glyph);
+    let position = crate::Point::new(
+        key.subpixel.0 as f32 / SUBPIXEL_STEPS,
+        key.subpixel.1 as f32 / SUBPIXEL_STEPS,
+    );
+    let bb = font.font.glyph_bounds(glyph.id, scale, position)?;
+    let pad = key.blur + 1;
+    let width = bb.width() + pad * 2;
+    let height = bb.height() + pad * 2;
+
+    let mut data = vec![0; width * height];
+    font.font.rasterize(
+        glyph.id,
+        scale,
+        position,
+        &mut data[pad * width + pad..],
+        width,
+    );
+    if key.blur > 0 {
+        blur(&mut data, width, height, key.blur);
+    }
+
+    let bounds = Bounds {
+        min: crate::Point::new(
+            (bb.min_x - pad as i32) as f32,
+            (bb.min_y - pad as i32) as f32,
+        ),
+        max: crate::Point::new(
+            (bb.max_x + pad as i32) as f32,
+            (bb.max_y + pad as i32) as f32,
+        ),
+    };
+    Some((data, width, height, bounds))
+}
+
+// Rasterizes at three times the horizontal resolution, the filtered subpixels are stored as
+// the RGB channels of each pixel.
+fn rasterize_lcd(
+    font: &FontData,
+    glyph: &PlacedGlyph,
+    key: GlyphKey,
+) -> Option<(Vec<u8>, usize, usize, Bounds)> {
+    // The FreeType default LCD filter.
+    const FILTER: [f32; 5] = [0.031, 0.302, 0.336, 0.302, 0.031];
+
+    let scale = font.glyph_scale(glyph.size);
+    let scale = GlyphScale {
+        x: scale.x * 3.0,
+        y: scale.y,
+    };
+    let position = crate::Point::new(
+        key.subpixel.0 as f32 / SUBPIXEL_STEPS * 3.0,
+        key.subpixel.1 as f32 / SUBPIXEL_STEPS,
+    );
+    let bb = font.font.glyph_bounds(glyph.id, scale, position)?;
+
+    // The filter spreads each subpixel over two neighbours on either side.
+    let min_x = (bb.min_x - 2).div_euclid(3);
+    let max_x = (bb.max_x + 4).div_euclid(3);
+    let width = (max_x - min_x) as usize;
+    let height = bb.height();
+    let stride = width * 3;
+    let offset = (bb.min_x - min_x * 3) as usize;
+
+    let mut subpixels = vec![0; stride * height];
+    font.font
+        .rasterize(glyph.id, scale, position, &mut subpixels[offset..], stride);
+
+    let mut data = vec![0; stride * height];
+    for (row, subpixels) in data
+        .chunks_exact_mut(stride)
+        .zip(subpixels.chunks_exact(stride))
+    {
+        for (x, value) in row.iter_mut().enumerate() {
+            let sum: f32 = FILTER
+                .iter()
+                .enumerate()
+                .filter_map(|(i, w)| {
+                    Some(w * *subpixels.get((x + i).checked_sub(2)?)? as f32 / 255.0)
+                })
+                .sum();
+            *value = (sum.min(1.0) * 255.0).round() as u8;
+        }
+    }
+
+    let bounds = Bounds {
+        min: crate::Point::new(min_x as f32, bb.min_y as f32),
+        max: crate::Point::new(max_x as f32, bb.max_y as f32),
+    };
+    Some((data, width, height, bounds))
+}
+
+fn rasterize_sdf(font: &FontData, glyph: &PlacedGlyph) -> Option<(Vec<u8>, usize, usize, Bounds)> {
+    let scale = font.glyph_scale(SDF_SIZE);
+    let bb = font
+        .font
+        .glyph_bounds(glyph.id, scale, Default::default())?;
+    let pad = SDF_SPREAD + 1;
+    let width = bb.width() + pad * 2;
+    let height = bb.height() + pad * 2;
+
+    let mut coverage = vec![0; width * height];
+    font.font.rasterize(
+        glyph.id,
+        scale,
+        Default::default(),
+        &mut coverage[pad * width + pad..],
+        width,
+    );
+
+    let inside = |x: isize, y: isize| {
+        x >= 0
+            && y >= 0
+            && (x as usize) < width
+            && (y as usize) < height
+            && coverage[y as usize * width + x as usize] >= 128
+    };
+    let spread = SDF_SPREAD as isize;
+    let mut data = vec![0; width * height];
+
+    // Brute force search for the nearest pixel on the other side of the outline, the edge
+    // itself is half a pixel away from it.
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let is_inside = inside(x, y);
+            let mut nearest = (spread * spread + 1) as f32;
+            for dy in -spread..=spread {
+                for dx in -spread..=spread {
+                    if inside(x + dx, y + dy) != is_inside {
+                        nearest = nearest.min((dx * dx + dy * dy) as f32);
+                    }
+                }
+            }
+            let distance = (nearest.sqrt() - 0.5).min(SDF_SPREAD as f32);
+            let distance = if is_inside { distance } else { -distance };
+            data[y as usize * width + x as usize] =
+                ((0.5 + distance / (SDF_SPREAD * 2) as f32) * 255.0).round() as u8;
+        }
+    }
+
+    let bounds = Bounds {
+        min: crate::Point::new(
+            (bb.min_x - pad as i32) as f32,
+            (bb.min_y - pad as i32) as f32,
+        ),
+        max: crate::Point::new(
+            (bb.max_x + pad as i32) as f32,
+            (bb.max_y + pad as i32) as f32,
+        ),
+    };
+    Some((data, width, height, bounds))
+}
+
+// Glyphs without pixels are drawn as empty quads at their position.
+fn skip_glyph(lc: &mut LayoutChar) {
+    lc.uv = Default::default();
+    lc.bounds = Bounds {
+        min: lc.glyph.position,
+        max: lc.glyph.position,
+    };
+}
+
+fn rasterize_color(
+    font: &FontData,
+    glyph: &PlacedGlyph,
+    key: GlyphKey,
+) -> Option<(Vec<u8>, usize, usize, Bounds)> {
+    if let Some(colr) = &font.colr {
+        if colr.contains(key.glyph) {
+            return rasterize_layers(font, colr, glyph);
+        }
+    }
+    if font.is_bitmap_glyph(key.glyph) {
+        let bitmap = rasterize_bitmap(font, glyph);
+        // Strikes in image formats other than PNG are not decoded, glyphs without an outline
+        // to fall back to have no raster and are skipped.
+        if bitmap.is_some() || glyph.bounds.is_none() {
+            return bitmap;
+        }
+    }
+
+    // Glyphs without color data are drawn in white, like the text color layers.
+    let (coverage, width, height, bounds) = rasterize(font, glyph, key)?;
+    // `repeat_n` needs Rust 1.82.
+    #[allow(clippy::manual_repeat_n)]
+    let data = coverage
+        .iter()
+        .flat_map(|v| std::iter::repeat(*v).take(4))
+        .collect();
+    Some((data, width, height, bounds))
+}
+
+fn rasterize_layers(
+    font: &FontData,
+    colr: &ColorLayers,
+    glyph: &PlacedGlyph,
+) -> Option<(Vec<u8>, usize, usize, Bounds)> {
+    let scale = font.glyph_scale(glyph.size);
+    let layers = colr
+        .layers(glyph.id)
+        .filter_map(|(id, color)| {
+            let bb = font.font.glyph_bounds(id, scale, Default::default())?;
+            Some((id, bb, color))
+        })
+        .collect::<Vec<_>>();
+
+    let min_x = layers.iter().map(|(_, bb, _)| bb.min_x).min()?;
+    let min_y = layers.iter().map(|(_, bb, _)| bb.min_y).min()?;
+    let max_x = layers.iter().map(|(_, bb, _)| bb.max_x).max()?;
+    let max_y = layers.iter().map(|(_, bb, _)| bb.max_y).max()?;
+    let pad = 1;
+    let width = (max_x - min_x) as usize + pad * 2;
+    let height = (max_y - min_y) as usize + pad * 2;
+
+    let mut pixels = vec![[0.0f32; 4]; width * height];
+    let mut coverage = Vec::new();
+    for (id, bb, color) in &layers {
+        coverage.clear();
+        coverage.resize(bb.width() * bb.height(), 0);
+        font.font
+            .rasterize(*id, scale, Default::default(), &mut coverage, bb.width());
+
+        let alpha = color[3] as f32 / 255.0;
+        for (n, v) in coverage.iter().enumerate() {
+            let x = (bb.min_x - min_x) as usize + n % bb.width() + pad;
+            let y = (bb.min_y - min_y) as usize + n / bb.width() + pad;
+            let pixel = &mut pixels[y * width + x];
+            let a = alpha * *v as f32 / 255.0;
+            for i in 0..3 {
+                pixel[i] = color[i] as f32 / 255.0 * a + pixel[i] * (1.0 - a);
+            }
+            pixel[3] = a + pixel[3] * (1.0 - a);
+        }
+    }
+
+    let data = pixels
+        .iter()
+        .flat_map(|pixel| pixel.iter().map(|v| (v * 255.0).round() as u8))
+        .collect();
+    let bounds = Bounds {
+        min: crate::Point::new((min_x - pad as i32) as f32, (min_y - pad as i32) as f32),
+        max: crate::Point::new((max_x + pad as i32) as f32, (max_y + pad as i32) as f32),
+    };
+    Some((data, width, height, bounds))
+}
+
+fn rasterize_bitmap(
+    font: &FontData,
+    glyph: &PlacedGlyph,
+) -> Option<(Vec<u8>, usize, usize, Bounds)> {
+    let face = ttf_parser::Face::from_slice(&font.data, font.index).ok()?;
+    let ppem =
+        glyph.size * face.units_per_em() as f32 / (face.ascender() - face.descender()) as f32;
+    let raster =
+        face.glyph_raster_image(ttf_parser::GlyphId(glyph.id), ppem.round().max(1.0) as u16)?;
+    if raster.format != ttf_parser::RasterImageFormat::PNG {
+        return None;
+    }
+
+    let image = image::load_from_memory_with_format(raster.data, image::ImageFormat::PNG)
+        .ok()?
+        .to_rgba();
+    let scale = ppem / raster.pixels_per_em as f32;
+    let image_width = ((image.width() as f32 * scale).round() as u32).max(1);
+    let image_height = ((image.height() as f32 * scale).round() as u32).max(1);
+    let image = image::imageops::resize(
+        &image,
+        image_width,
+        image_height,
+        image::FilterType::Triangle,
+    );
+
+    let pad = 1;
+    let width = image_width as usize + pad * 2;
+    let height = image_height as usize + pad * 2;
+    let mut data = vec![0; width * height * 4];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        let offset = ((y as usize + pad) * width + x as usize + pad) * 4;
+        data[offset] = (r as u32 * a as u32 / 255) as u8;
+        data[offset + 1] = (g as u32 * a as u32 / 255) as u8;
+        data[offset + 2] = (b as u32 * a as u32 / 255) as u8;
+        data[offset + 3] = a;
+    }
+
+    // The image offsets are relative to the baseline with the y axis pointing up.
+    let min_x = (raster.x as f32 * scale).round();
+    let max_y = -(raster.y as f32 * scale).round();
+    let bounds = Bounds {
+        min: crate::Point::new(min_x - pad as f32, max_y - image_height as f32 - pad as f32),
+        max: crate::Point::new(min_x + image_width as f32 + pad as f32, max_y + pad as f32),
+    };
+    Some((data, width, height, bounds))
+}
+
+fn blur_cols(data: &mut [u8], width: usize, height: usize, alpha: i32) {
+    for y in 0..height {
+        let row = &mut data[y * width..(y + 1) * width];
+        let mut z = 0;
+        for v in row.iter_mut().skip(1) {
+            z += (alpha * (((*v as i32) << ZPREC) - z)) >> APREC;
+            *v = (z >> ZPREC) as u8;
+        }
+        row[width - 1] = 0;
+        z = 0;
+        for v in row[..width - 1].iter_mut().rev() {
+            z += (alpha * (((*v as i32) << ZPREC) - z)) >> APREC;
+            *v = (z >> ZPREC) as u8;
+        }
+        row[0] = 0;
+    }
+}
+
+fn blur_rows(data: &mut [u8], width: usize, height: usize, alpha: i32) {
+    for x in 0..width {
+        let mut z = 0;
+        for y in 1..height {
+            let v = &mut data[y * width + x];
+            z += (alpha * (((*v as i32) << ZPREC) - z)) >> APREC;
+            *v = (z >> ZPREC) as u8;
+        }
+        data[(height - 1) * width + x] = 0;
+        z = 0;
+        for y in (0..height - 1).rev() {
+            let v = &mut data[y * width + x];
+            z += (alpha * (((*v as i32) << ZPREC) - z)) >> APREC;
+            *v = (z >> ZPREC) as u8;
+        }
+        data[x] = 0;
+    }
+}
+
+fn blur(data: &mut [u8], width: usize, height: usize, radius: usize) {
+    // Approximates a gaussian blur with two passes of a recursive exponential filter.
+    let sigma = radius as f32 * 0.57735;
+    let alpha = ((1 << APREC) as f32 * (1.0 - (-2.3 / (sigma + 1.0)).exp())) as i32;
+    blur_rows(data, width, height, alpha);
+    blur_cols(data, width, height, alpha);
+    blur_rows(data, width, height, alpha);
+    blur_cols(data, width, height, alpha);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Characters ten units wide.
+    fn monospace(text: &str) -> Vec<(usize, char, f32, f32, f32, f32)> {
+        text.char_indices()
+            .enumerate()
+            .map(|(n, (idx, c))| {
+                let x = n as f32 * 10.0;
+                (idx, c, x, x + 10.0, x, x + 10.0)
+            })
+            .collect()
+    }
+
+    fn wrapped(text: &str, break_width: f32) -> Vec<&str> {
+        let mut rows = Vec::new();
+        wrap_lines(text, &monospace(text), break_width, &mut rows);
+        rows.iter().map(|row| &text[row.start..row.end]).collect()
+    }
+
+    #[test]
+    fn wrap_lines_breaks_between_words() {
+        assert_eq!(wrapped("aaa bbb ccc", 75.0), ["aaa bbb", "ccc"]);
+        assert_eq!(wrapped("aaa bbb ccc", 200.0), ["aaa bbb ccc"]);
+    }
+
+    #[test]
+    fn wrap_lines_breaks_long_words() {
+        assert_eq!(wrapped("abcdefgh", 35.0), ["abc", "def", "gh"]);
+    }
+
+    #[test]
+    fn wrap_lines_keeps_empty_lines() {
+        assert_eq!(wrapped("aa\n\nbb", 100.0), ["aa", "", "bb"]);
+    }
+
+    fn closest_weight(desired: u16, weights: &[u16]) -> u16 {
+        *weights
+            .iter()
+            .min_by_key(|weight| weight_distance(desired, **weight))
+            .unwrap()
+    }
+
+    #[test]
+    fn weight_distance_follows_css_matching() {
+        assert_eq!(closest_weight(400, &[300, 400, 700]), 400);
+        assert_eq!(closest_weight(400, &[300, 500, 700]), 500);
+        assert_eq!(closest_weight(450, &[300, 400, 600]), 400);
+        assert_eq!(closest_weight(500, &[300, 400, 600]), 400);
+        assert_eq!(closest_weight(600, &[400, 500, 800]), 800);
+        assert_eq!(closest_weight(600, &[400, 500]), 500);
+        assert_eq!(closest_weight(300, &[100, 400, 500]), 100);
+        assert_eq!(closest_weight(300, &[400, 500]), 400);
+    }
+
+    #[test]
+    fn truncate_fits_with_the_ellipsis() {
+        let text = "aaa bbb ccc ddd";
+        let width = |range: Range<usize>| text[range].chars().count() as f32 * 10.0;
+        let truncated = |max_width, mode| truncate(text, width, 10.0, max_width, mode);
+
+        let full = truncated(200.0, TruncationMode::End);
+        assert!(!full.is_truncated());
+
+        let end = truncated(80.0, TruncationMode::End);
+        assert_eq!((end.head, end.tail), (0..7, 15..15));
+        let start = truncated(80.0, TruncationMode::Start);
+        assert_eq!((start.head, start.tail), (0..0, 8..15));
+
+        // The tail gets the width the head leaves, without leading spaces.
+        let middle = truncated(80.0, TruncationMode::Middle);
+        assert_eq!((middle.head, middle.tail), (0..3, 12..15));
+
+        let none = truncated(5.0, TruncationMode::End);
+        assert_eq!(none.head, 0..0);
+    }
+
+    #[cfg(all(feature = "rusttype", feature = "system-fonts"))]
+    #[test]
+    fn text_size_loads_system_fallbacks() {
+        struct MockRenderer;
+
+        impl Renderer for MockRenderer {
+            fn edge_antialias(&self) -> bool {
+                true
+            }
+
+            fn create_texture(
+                &mut self,
+                _texture_type: TextureType,
+                _width: usize,
+                _height: usize,
+                _flags: ImageFlags,
+                _data: Option<&[u8]>,
+            ) -> anyhow::Result<ImageId> {
+                Ok(1)
+            }
+
+            fn delete_texture(&mut self, _img: ImageId) -> anyhow::Result<()> {
+                Ok(())
+            }
+
+            fn update_texture(
+                &mut self,
+                _img: ImageId,
+                _x: usize,
+                _y: usize,
+                _width: usize,
+                _height: usize,
+                _data: &[u8],
+            ) -> anyhow::Result<()> {
+                Ok(())
+            }
+
+            fn texture_size(&self, _img: ImageId) -> anyhow::Result<(usize, usize)> {
+                Ok((512, 512))
+            }
+
+            fn viewport(&mut self, _extent: Extent, _ratio: f32) -> anyhow::Result<()> {
+                Ok(())
+            }
+
+            fn cancel(&mut self) -> anyhow::Result<()> {
+                Ok(())
+            }
+
+            fn flush(&mut self) -> anyhow::Result<()> {
+                Ok(())
+            }
+
+            fn fill(
+                &mut self,
+                _paint: &crate::Paint,
+                _composite_operation: crate::context::CompositeOperationState,
+                _scissor: &crate::renderer::Scissor,
+                _fringe: f32,
+                _bounds: Bounds,
+                _paths: &[crate::context::Path],
+            ) -> anyhow::Result<()> {
+                Ok(())
+            }
+
+            fn stroke(
+                &mut self,
+                _paint: &crate::Paint,
+                _composite_operation: crate::context::CompositeOperationState,
+                _scissor: &crate::renderer::Scissor,
+                _fringe: f32,
+                _stroke_width: f32,
+                _paths: &[crate::context::Path],
+            ) -> anyhow::Result<()> {
+                Ok(())
+            }
+
+            fn triangles(
+                &mut self,
+                _paint: &crate::Paint,
+                _composite_operation: crate::context::CompositeOperationState,
+                _scissor: &crate::renderer::Scissor,
+                _vertexes: &[crate::context::Vertex],
+            ) -> anyhow::Result<()> {
+                Ok(())
+            }
+        }
+
+        let dir = std::path::Path::new("/usr/share/fonts");
+        if !dir.is_dir() {
+            return;
+        }
+        let backend = Box::new(crate::RusttypeBackend);
+        let mut fonts = Fonts::new(&mut MockRenderer, (512, 512), (512, 512), backend).unwrap();
+        let roboto = include_bytes!("../nvg-gl/examples/Roboto-Bold.ttf");
+        let id = fonts.add_font("roboto", roboto.as_ref(), 0).unwrap();
+        fonts.load_fonts_dir(dir);
+
+        let spacing = TextSpacing::default();
+        let text = "abc \u{2211}\u{5d0}";
+        let size = fonts.text_size(text, id, 16.0, &spacing);
+        assert!(size.width > fonts.text_size("abc ", id, 16.0, &spacing).width);
+        assert!(fonts
+            .align_offset(text, id, 16.0, Align::CENTER, &spacing)
+            .is_some());
+    }
+
+    #[test]
+    fn quantize_axis_snaps_to_range_steps() {
+        assert_eq!(quantize_axis(400.0, 300.0, 700.0), 400.0);
+        assert_eq!(quantize_axis(400.3, 300.0, 700.0), 400.0);
+        assert_eq!(quantize_axis(401.0, 300.0, 700.0), 401.5625);
+        assert_eq!(quantize_axis(900.0, 300.0, 700.0), 700.0);
+        assert_eq!(quantize_axis(5.0, 1.0, 1.0), 1.0);
+    }
+
+    #[cfg(all(feature = "rusttype", not(feature = "shaping")))]
+    #[test]
+    fn features_apply_single_substitutions() {
+        let roboto = include_bytes!("../nvg-gl/examples/Roboto-Bold.ttf");
+        let fd = FontData::new(&crate::RusttypeBackend, roboto.to_vec(), 0).unwrap();
+        let e = fd.font.glyph_index('e').unwrap();
+        assert_ne!(fd.substitute(e, &[(*b"smcp", true)]), e);
+        assert_eq!(fd.substitute(e, &[(*b"smcp", false)]), e);
+        assert_eq!(fd.substitute(e, &[]), e);
+        assert_eq!(fd.substitutions.borrow().len(), 1);
     }
 }
@@ -1,18 +1,33 @@
 #[macro_use]
 extern crate bitflags;
 
+mod atlas;
 mod cache;
 mod color;
+mod colr;
 mod context;
+pub mod font_backend;
+#[cfg(feature = "system-fonts")]
+mod fontdb;
 mod fonts;
 mod math;
 pub mod renderer;
+mod rich_text;
+#[cfg(feature = "rusttype")]
+mod rusttype_backend;
 
 pub use color::*;
 pub use context::{
     Align, BasicCompositeOperation, BlendFactor, CompositeOperation, Context, Gradient, ImageFlags,
-    ImageId, ImagePattern, LineCap, LineJoin, Paint, Solidity, TextMetrics,
+    ImageId, ImagePattern, LineCap, LineJoin, Paint, Solidity, TextDecoration, TextMetrics,
+    TextRendering, WritingMode,
+};
+pub use font_backend::FontBackend;
+pub use fonts::{
+    FontId, FontInfo, GlyphPosition, TextLayout, TextRow, TruncatedText, TruncationMode,
 };
-pub use fonts::FontId;
 pub use math::*;
 pub use renderer::Renderer;
+pub use rich_text::{RichText, RichTextRow, TextOffset, TextSpan, TextStyle};
+#[cfg(feature = "rusttype")]
+pub use rusttype_backend::RusttypeBackend;
@@ -4,6 +4,7 @@ pub use crate::*;
 #[derive(Debug, Copy, Clone)]
 pub enum TextureType {
     RGBA,
+    RGB,
     Alpha,
 }
 
@@ -72,4 +73,21 @@ pub trait Renderer {
         scissor: &Scissor,
         vertexes: &[Vertex],
     ) -> anyhow::Result<()>;
+
+    // Draws glyph quads filled with `paint`, the texture coordinates sample the glyph coverage
+    // from `mask`. Renderers without masking draw the text in the inner color of the paint.
+    fn text(
+        &mut self,
+        paint: &Paint,
+        composite_operation: CompositeOperationState,
+        scissor: &Scissor,
+        mask: ImageId,
+        vertexes: &[Vertex],
+    ) -> anyhow::Result<()> {
+        let paint = Paint {
+            image: Some(mask),
+            ..*paint
+        };
+        self.triangles(&paint, composite_operation, scissor, vertexes)
+    }
 }
@@ -0,0 +1,96 @@
+use crate::{FontId, Paint};
+
+#[derive(Debug, Copy, Clone)]
+pub struct TextStyle {
+    pub font_id: FontId,
+    pub font_size: f32,
+    pub fill: Paint,
+    pub letter_spacing: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextSpan {
+    pub text: String,
+    pub style: TextStyle,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RichText {
+    pub spans: Vec<TextSpan>,
+}
+
+impl RichText {
+    pub fn new() -> RichText {
+        Default::default()
+    }
+
+    pub fn push<S: Into<String>>(&mut self, text: S, style: TextStyle) -> &mut Self {
+        self.spans.push(TextSpan {
+            text: text.into(),
+            style,
+        });
+        self
+    }
+
+    pub(crate) fn offset(&self, idx: usize) -> TextOffset {
+        let mut start = 0;
+        for (span, s) in self.spans.iter().enumerate() {
+            if idx < start + s.text.len() {
+                return TextOffset {
+                    span,
+                    idx: idx - start,
+                };
+            }
+            start += s.text.len();
+        }
+
+        match self.spans.last() {
+            Some(s) => TextOffset {
+                span: self.spans.len() - 1,
+                idx: s.text.len(),
+            },
+            None => Default::default(),
+        }
+    }
+
+    pub(crate) fn segments(
+        &self,
+        start: TextOffset,
+        end: TextOffset,
+    ) -> impl Iterator<Item = (usize, usize, &str)> + '_ {
+        self.spans
+            .iter()
+            .enumerate()
+            .take(end.span + 1)
+            .skip(start.span)
+            .map(move |(n, span)| {
+                let from = if n == start.span { start.idx } else { 0 };
+                let to = if n == end.span {
+                    end.idx
+                } else {
+                    span.text.len()
+                };
+                (n, from, &span.text[from..to])
+            })
+            .filter(|(_, _, text)| !text.is_empty())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct TextOffset {
+    pub span: usize,
+    pub idx: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RichTextRow {
+    pub start: TextOffset,
+    pub end: TextOffset,
+    pub next: TextOffset,
+    pub width: f32,
+    pub min_x: f32,
+    pub max_x: f32,
+    pub ascender: f32,
+    pub descender: f32,
+    pub height: f32,
+}
@@ -0,0 +1,399 @@
+use crate::font_backend::{
+    Contour, FaceMetrics, FontBackend, FontFace, GlyphId, GlyphScale, PixelBounds, Segment, Tag,
+};
+use crate::Point;
+use ab_glyph_rasterizer::Rasterizer;
+use rusttype::{Font, FontCollection, PositionedGlyph, Scale};
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RusttypeBackend;
+
+impl FontBackend for RusttypeBackend {
+    fn load_face(&self, data: Arc<[u8]>, index: u32) -> anyhow::Result<Box<dyn FontFace>> {
+        // Rusttype rejects fonts without `glyf` or `CFF ` outlines, like CBDT bitmap fonts and
+        // CFF2 variable fonts, those are read with ttf-parser only.
+        let font = FontCollection::<'static>::from_bytes(data.clone())
+            .and_then(|collection| collection.font_at(index as usize))
+            .ok();
+        let (metrics, kern) = match &font {
+            Some(font) => {
+                let v_metrics = font.v_metrics_unscaled();
+                let metrics = FaceMetrics {
+                    ascent: v_metrics.ascent,
+                    descent: v_metrics.descent,
+                    line_gap: v_metrics.line_gap,
+                };
+                (metrics, None)
+            }
+            None => {
+                let face = ttf_parser::Face::from_slice(&data, index)?;
+                let metrics = FaceMetrics {
+                    ascent: face.ascender() as f32,
+                    descent: face.descender() as f32,
+                    line_gap: face.line_gap() as f32,
+                };
+                (metrics, kern_table(&data, &face))
+            }
+        };
+        Ok(Box::new(RusttypeFace {
+            font,
+            metrics,
+            data,
+            index,
+            kern,
+            variations: Vec::new(),
+            parsed_glyphs: Default::default(),
+            glyph_ids: Default::default(),
+        }))
+    }
+}
+
+// A glyph read with ttf-parser in font units, y grows upwards.
+struct ParsedGlyph {
+    advance: Option<f32>,
+    contours: Vec<Contour>,
+}
+
+struct RusttypeFace {
+    font: Option<Font<'static>>,
+    metrics: FaceMetrics,
+    data: Arc<[u8]>,
+    index: u32,
+    // Where the `kern` table is in the data, for fonts rusttype cannot read.
+    kern: Option<Range<usize>>,
+    // Rusttype only reads the default instance, the glyphs of other instances and of fonts
+    // rusttype cannot read are read with ttf-parser and rasterized here.
+    variations: Vec<(Tag, f32)>,
+    parsed_glyphs: RefCell<HashMap<GlyphId, ParsedGlyph>>,
+    glyph_ids: RefCell<HashMap<char, Option<GlyphId>>>,
+}
+
+#[derive(Default)]
+struct OutlineBuilder {
+    contours: Vec<Contour>,
+    contour: Contour,
+    start: Point,
+    last: Point,
+}
+
+impl ttf_parser::OutlineBuilder for OutlineBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.close();
+        self.start = Point::new(x, y);
+        self.last = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let pt = Point::new(x, y);
+        self.contour.push(Segment::Line(self.last, pt));
+        self.last = pt;
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let pt = Point::new(x, y);
+        self.contour
+            .push(Segment::Quad(self.last, Point::new(x1, y1), pt));
+        self.last = pt;
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let pt = Point::new(x, y);
+        self.contour.push(Segment::Cubic(
+            self.last,
+            Point::new(x1, y1),
+            Point::new(x2, y2),
+            pt,
+        ));
+        self.last = pt;
+    }
+
+    // Outlines may leave contours open, CFF2 outlines do not close the last one.
+    fn close(&mut self) {
+        if self.contour.is_empty() {
+            return;
+        }
+        if self.last.x != self.start.x || self.last.y != self.start.y {
+            self.contour.push(Segment::Line(self.last, self.start));
+        }
+        self.contours.push(std::mem::take(&mut self.contour));
+    }
+}
+
+fn kern_table(data: &[u8], face: &ttf_parser::Face) -> Option<Range<usize>> {
+    let table = face.table_data(ttf_parser::Tag::from_bytes(b"kern"))?;
+    let start = table.as_ptr() as usize - data.as_ptr() as usize;
+    Some(start..start + table.len())
+}
+
+fn map_segment<F: Fn(Point) -> Point>(segment: &Segment, f: F) -> Segment {
+    match *segment {
+        Segment::Line(p0, p1) => Segment::Line(f(p0), f(p1)),
+        Segment::Quad(p0, p1, p2) => Segment::Quad(f(p0), f(p1), f(p2)),
+        Segment::Cubic(p0, p1, p2, p3) => Segment::Cubic(f(p0), f(p1), f(p2), f(p3)),
+    }
+}
+
+fn segment_points(segment: &Segment) -> Vec<Point> {
+    match *segment {
+        Segment::Line(p0, p1) => vec![p0, p1],
+        Segment::Quad(p0, p1, p2) => vec![p0, p1, p2],
+        Segment::Cubic(p0, p1, p2, p3) => vec![p0, p1, p2, p3],
+    }
+}
+
+impl RusttypeFace {
+    fn height(&self) -> f32 {
+        self.metrics.ascent - self.metrics.descent
+    }
+
+    // The rusttype font if it reads the glyphs of this face.
+    fn rusttype_font(&self) -> Option<&Font<'static>> {
+        self.font.as_ref().filter(|_| self.variations.is_empty())
+    }
+
+    // Rusttype scales to the height from the descent to the ascent in pixels.
+    fn positioned(
+        &self,
+        font: &Font<'static>,
+        glyph: GlyphId,
+        scale: GlyphScale,
+        position: Point,
+    ) -> PositionedGlyph<'static> {
+        font.glyph(rusttype::GlyphId(glyph as u32))
+            .scaled(Scale {
+                x: scale.x * self.height(),
+                y: scale.y * self.height(),
+            })
+            .positioned(rusttype::point(position.x, position.y))
+    }
+
+    fn face(&self) -> Option<ttf_parser::Face<'_>> {
+        let mut face = ttf_parser::Face::from_slice(&self.data, self.index).ok()?;
+        for (tag, value) in &self.variations {
+            face.set_variation(ttf_parser::Tag::from_bytes(tag), *value);
+        }
+        Some(face)
+    }
+
+    fn parsed_glyph(&self, glyph: GlyphId) -> Ref<'_, ParsedGlyph> {
+        if !self.parsed_glyphs.borrow().contains_key(&glyph) {
+            let mut builder = OutlineBuilder::default();
+            let mut advance = None;
+            if let Some(face) = self.face() {
+                face.outline_glyph(ttf_parser::GlyphId(glyph), &mut builder);
+                ttf_parser::OutlineBuilder::close(&mut builder);
+                advance = face
+                    .glyph_hor_advance(ttf_parser::GlyphId(glyph))
+                    .map(|advance| advance as f32);
+            }
+            self.parsed_glyphs.borrow_mut().insert(
+                glyph,
+                ParsedGlyph {
+                    advance,
+                    contours: builder.contours,
+                },
+            );
+        }
+        Ref::map(self.parsed_glyphs.borrow(), |glyphs| &glyphs[&glyph])
+    }
+
+    // The contours of a glyph read with ttf-parser in pixels.
+    fn parsed_outline(&self, glyph: GlyphId, scale: GlyphScale, position: Point) -> Vec<Contour> {
+        let transform =
+            |pt: Point| Point::new(position.x + pt.x * scale.x, position.y - pt.y * scale.y);
+        self.parsed_glyph(glyph)
+            .contours
+            .iter()
+            .map(|contour| {
+                contour
+                    .iter()
+                    .map(|segment| map_segment(segment, transform))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl FontFace for RusttypeFace {
+    fn metrics(&self) -> FaceMetrics {
+        self.metrics
+    }
+
+    fn glyph_index(&self, c: char) -> Option<GlyphId> {
+        if let Some(font) = &self.font {
+            return match font.glyph(c).id().0 {
+                0 => None,
+                id => Some(id as GlyphId),
+            };
+        }
+
+        if let Some(id) = self.glyph_ids.borrow().get(&c) {
+            return *id;
+        }
+        let id = self
+            .face()
+            .and_then(|face| face.glyph_index(c))
+            .map(|id| id.0)
+            .filter(|id| *id != 0);
+        self.glyph_ids.borrow_mut().insert(c, id);
+        id
+    }
+
+    fn advance(&self, glyph: GlyphId) -> f32 {
+        match self.rusttype_font() {
+            Some(font) => {
+                font.glyph(rusttype::GlyphId(glyph as u32))
+                    .scaled(Scale::uniform(self.height()))
+                    .h_metrics()
+                    .advance_width
+            }
+            None => self.parsed_glyph(glyph).advance.unwrap_or_default(),
+        }
+    }
+
+    fn kerning(&self, left: GlyphId, right: GlyphId) -> f32 {
+        if let Some(font) = &self.font {
+            return font.pair_kerning(
+                Scale::uniform(self.height()),
+                rusttype::GlyphId(left as u32),
+                rusttype::GlyphId(right as u32),
+            );
+        }
+
+        self.kern
+            .clone()
+            .and_then(|range| ttf_parser::kern::Table::parse(&self.data[range]))
+            .and_then(|kern| {
+                kern.subtables
+                    .into_iter()
+                    .filter(|subtable| subtable.horizontal && !subtable.variable)
+                    .find_map(|subtable| {
+                        subtable
+                            .glyphs_kerning(ttf_parser::GlyphId(left), ttf_parser::GlyphId(right))
+                    })
+            })
+            .map(|kerning| kerning as f32)
+            .unwrap_or_default()
+    }
+
+    fn glyph_bounds(
+        &self,
+        glyph: GlyphId,
+        scale: GlyphScale,
+        position: Point,
+    ) -> Option<PixelBounds> {
+        if let Some(font) = self.rusttype_font() {
+            let bb = self
+                .positioned(font, glyph, scale, position)
+                .pixel_bounding_box()?;
+            return Some(PixelBounds {
+                min_x: bb.min.x,
+                min_y: bb.min.y,
+                max_x: bb.max.x,
+                max_y: bb.max.y,
+            });
+        }
+
+        let mut bounds: Option<(Point, Point)> = None;
+        for pt in self
+            .parsed_outline(glyph, scale, position)
+            .iter()
+            .flatten()
+            .flat_map(segment_points)
+        {
+            bounds = Some(match bounds {
+                Some((min, max)) => (
+                    Point::new(min.x.min(pt.x), min.y.min(pt.y)),
+                    Point::new(max.x.max(pt.x), max.y.max(pt.y)),
+                ),
+                None => (pt, pt),
+            });
+        }
+        let (min, max) = bounds?;
+        let bb = PixelBounds {
+            min_x: min.x.floor() as i32,
+            min_y: min.y.floor() as i32,
+            max_x: max.x.ceil() as i32,
+            max_y: max.y.ceil() as i32,
+        };
+        if bb.max_x > bb.min_x && bb.max_y > bb.min_y {
+            Some(bb)
+        } else {
+            None
+        }
+    }
+
+    fn rasterize(
+        &self,
+        glyph: GlyphId,
+        scale: GlyphScale,
+        position: Point,
+        coverage: &mut [u8],
+        stride: usize,
+    ) {
+        if let Some(font) = self.rusttype_font() {
+            self.positioned(font, glyph, scale, position)
+                .draw(|x, y, v| {
+                    coverage[y as usize * stride + x as usize] = (v * 255.0).round() as u8;
+                });
+            return;
+        }
+
+        let bb = match self.glyph_bounds(glyph, scale, position) {
+            Some(bb) => bb,
+            None => return,
+        };
+        let point =
+            |pt: Point| ab_glyph_rasterizer::point(pt.x - bb.min_x as f32, pt.y - bb.min_y as f32);
+        let mut rasterizer = Rasterizer::new(bb.width(), bb.height());
+        for segment in self.parsed_outline(glyph, scale, position).iter().flatten() {
+            match *segment {
+                Segment::Line(p0, p1) => rasterizer.draw_line(point(p0), point(p1)),
+                Segment::Quad(p0, p1, p2) => rasterizer.draw_quad(point(p0), point(p1), point(p2)),
+                Segment::Cubic(p0, p1, p2, p3) => {
+                    rasterizer.draw_cubic(point(p0), point(p1), point(p2), point(p3))
+                }
+            }
+        }
+        rasterizer.for_each_pixel_2d(|x, y, v| {
+            coverage[y as usize * stride + x as usize] = (v.min(1.0) * 255.0).round() as u8;
+        });
+    }
+
+    fn outline(&self, glyph: GlyphId, scale: GlyphScale, position: Point) -> Vec<Contour> {
+        let font = match self.rusttype_font() {
+            Some(font) => font,
+            None => return self.parsed_outline(glyph, scale, position),
+        };
+
+        let point = |p: rusttype::Point<f32>| Point::new(p.x, p.y);
+        self.positioned(font, glyph, scale, position)
+            .shape()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|contour| {
+                contour
+                    .segments
+                    .into_iter()
+                    .map(|segment| match segment {
+                        rusttype::Segment::Line(line) => {
+                            Segment::Line(point(line.p[0]), point(line.p[1]))
+                        }
+                        rusttype::Segment::Curve(curve) => {
+                            Segment::Quad(point(curve.p[0]), point(curve.p[1]), point(curve.p[2]))
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn set_variations(&mut self, variations: &[(Tag, f32)]) {
+        self.variations = variations.to_vec();
+        self.parsed_glyphs.get_mut().clear();
+    }
+}